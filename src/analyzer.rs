@@ -0,0 +1,568 @@
+use crate::cfg::CfgSet;
+use crate::compat::{classify_changes, Compatibility, WireFormat};
+use crate::diagnostic::Severity;
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::diff::{diff_types, Change};
+use crate::paths::PathRules;
+use crate::report::{CheckReport, FeatureSchema, MatrixReport, RootReport, TypeListing, TypeQuery};
+use crate::schema::{Schema, TypeSchema};
+use crate::visitor::SynVisitor;
+use crate::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The enum name used as store root when none is configured.
+pub const DEFAULT_ROOT: &str = "KeyValue";
+
+/// Well-known primitive types and external crate types that are not expected
+/// to be defined in the scanned source directories: the default of
+/// `Config::external_types`.
+pub const DEFAULT_EXTERNAL_TYPES: &[&str] = &[
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "f32",
+    "f64",
+    "bool",
+    "String",
+    "str",
+    "usize",
+    "isize",
+    "Option",
+    "Vec",
+    "HashMap",
+    "HashSet",
+    "BTreeMap",
+    "BTreeSet",
+    "Box",
+    "Arc",
+    "Rc",
+    "Cow",
+    "PhantomData",
+    "Duration",
+    // External crate types commonly seen in KeyValue
+    "PeerId",
+    "OutPoint",
+];
+
+/// An enum whose variants are persisted to the store. Every type reachable
+/// from its variants is part of the root's schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    /// The enum name, like `KeyValue`, or a path to it, like
+    /// `store::KeyValue`, to tell apart enums with the same name.
+    pub name: String,
+    /// The root's schema file. Defaults to `Config::default_schema_path`.
+    pub schema: Option<PathBuf>,
+}
+
+impl Root {
+    pub fn new(name: impl Into<String>) -> Self {
+        Root {
+            name: name.into(),
+            schema: None,
+        }
+    }
+
+    /// The enum name without its module path.
+    pub fn enum_name(&self) -> &str {
+        self.name.rsplit("::").next().unwrap_or_default().trim()
+    }
+}
+
+/// What to do with a source file that cannot be parsed or is not UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Skip the file, with a warning.
+    #[default]
+    Skip,
+    /// Skip the file, with an error that fails the check.
+    Strict,
+    /// Parse the file item by item and analyze every item that parses, with
+    /// a warning per skipped item. Invalid UTF-8 is replaced.
+    Recover,
+}
+
+impl fmt::Display for ParseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseMode::Skip => write!(f, "skip"),
+            ParseMode::Strict => write!(f, "strict"),
+            ParseMode::Recover => write!(f, "recover"),
+        }
+    }
+}
+
+impl FromStr for ParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ParseMode::Skip),
+            "strict" => Ok(ParseMode::Strict),
+            "recover" => Ok(ParseMode::Recover),
+            _ => Err(format!(
+                "unknown parse mode `{}`, expected one of: skip, strict, recover",
+                s
+            )),
+        }
+    }
+}
+
+/// Checks on the types of RPC code, see `PathRole::Rpc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcRules {
+    /// Field types, plain or in an `Option`, that must be serialized as
+    /// hex, e.g. with `#[serde_as(as = "U64Hex")]` for `u64`. Defaults to
+    /// the unsigned integers.
+    pub hex_types: Vec<String>,
+}
+
+impl Default for RpcRules {
+    fn default() -> Self {
+        RpcRules {
+            hex_types: ["u8", "u16", "u32", "u64", "u128"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Options for an analysis run.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Source code directories to scan.
+    pub source_dirs: Vec<String>,
+    /// Types crate source directory. When set, every store-reachable type
+    /// must be defined within it.
+    pub types_dir: Option<String>,
+    /// Store roots, each checked against its own schema file. Defaults to
+    /// a single `KeyValue` root.
+    pub roots: Vec<Root>,
+    /// The schema file of the first root when no roots are configured, as
+    /// given with `-o`: that of `KeyValue`, or of the first enum declared
+    /// with `#[migration_root]` if the source has no `KeyValue`.
+    pub output: Option<PathBuf>,
+    /// The encoding of store values. When set, each structural change is
+    /// classified for it, and a fingerprint change fails the check only if
+    /// some change needs a migration.
+    pub wire_format: Option<WireFormat>,
+    /// Severity overrides keyed by diagnostic name (e.g.
+    /// `store-type-added`) or code (e.g. `MC0007`).
+    pub severities: BTreeMap<String, Severity>,
+    /// The cfg options to build with. When set, items, fields and variants
+    /// behind an inactive `#[cfg]` are dropped and active `cfg_attr`s are
+    /// applied. Otherwise everything is scanned as one, except inline
+    /// modules behind a `#[cfg]` that no options satisfy, like `#[cfg(test)]`.
+    pub cfg: Option<CfgSet>,
+    /// How to handle source files that fail to parse.
+    pub parse_mode: ParseMode,
+    /// Which files under the source directories are scanned, and which
+    /// of them are RPC code.
+    pub paths: PathRules,
+    /// Types defined outside the scanned source, such as `u64` or `PeerId`,
+    /// or by path like `vendor::Pubkey` for a type in the scanned source:
+    /// their fields are not followed and they need not be in the types dir.
+    /// Defaults to `DEFAULT_EXTERNAL_TYPES`.
+    pub external_types: Vec<String>,
+    /// Checks on RPC code.
+    pub rpc: RpcRules,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            source_dirs: vec![],
+            types_dir: None,
+            roots: vec![],
+            output: None,
+            wire_format: None,
+            severities: BTreeMap::new(),
+            cfg: None,
+            parse_mode: ParseMode::default(),
+            paths: PathRules::default(),
+            external_types: DEFAULT_EXTERNAL_TYPES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            rpc: RpcRules::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn new(source_dirs: Vec<String>) -> Self {
+        Config {
+            source_dirs,
+            ..Default::default()
+        }
+    }
+
+    /// The configured roots, or the default `KeyValue` root.
+    pub fn roots(&self) -> Vec<Root> {
+        if self.roots.is_empty() {
+            vec![Root::new(DEFAULT_ROOT)]
+        } else {
+            self.roots.clone()
+        }
+    }
+
+    /// The schema file used for a single root when none is given
+    /// explicitly: `<first source dir>.schema.json`.
+    pub fn default_schema_path(&self) -> PathBuf {
+        let mut path = self.source_dirs.first().cloned().unwrap_or_default();
+        path.push_str(".schema.json");
+        PathBuf::from(path)
+    }
+
+    /// The schema file of `root`. With several roots, the default is
+    /// `<first source dir>.<enum name>.schema.json`.
+    pub fn schema_path(&self, root: &Root) -> PathBuf {
+        if let Some(schema) = &root.schema {
+            return schema.clone();
+        }
+        if self.roots.len() <= 1 {
+            return self.default_schema_path();
+        }
+        let mut path = self.source_dirs.first().cloned().unwrap_or_default();
+        path.push_str(&format!(".{}.schema.json", root.enum_name()));
+        PathBuf::from(path)
+    }
+
+    /// The schema file to check `root` against: its own, or, if that does
+    /// not exist yet, the single-root file the first root used before other
+    /// roots were added, e.g. by declaring a `#[migration_root]`.
+    pub fn baseline_schema_path(&self, root: &Root) -> PathBuf {
+        let path = self.schema_path(root);
+        if !path.exists() && root.schema.is_none() && self.roots().first() == Some(root) {
+            let single = self.default_schema_path();
+            if single.exists() {
+                return single;
+            }
+        }
+        path
+    }
+
+    /// The severity for `diagnostic`, taking overrides into account.
+    pub fn severity_for(&self, diagnostic: &Diagnostic) -> Severity {
+        self.severities
+            .get(diagnostic.kind.name())
+            .or_else(|| self.severities.get(diagnostic.code))
+            .copied()
+            .unwrap_or(diagnostic.severity)
+    }
+}
+
+/// Pairs the types of `old` and `new` whose key changed only because a
+/// type of the same name was added or removed elsewhere, which turns
+/// `Config` into `store::Config` or back, see the `SynVisitor` docs. Only
+/// unambiguous pairs are made.
+fn moved_keys<'a>(old: &'a Schema, new: &'a Schema) -> BTreeMap<&'a str, &'a str> {
+    let same_type =
+        |a: &str, b: &str| a.ends_with(&format!("::{}", b)) || b.ends_with(&format!("::{}", a));
+    let old_only: Vec<&str> = old
+        .fingerprints
+        .keys()
+        .filter(|key| !new.fingerprints.contains_key(*key))
+        .map(String::as_str)
+        .collect();
+    let new_only: Vec<&str> = new
+        .fingerprints
+        .keys()
+        .filter(|key| !old.fingerprints.contains_key(*key))
+        .map(String::as_str)
+        .collect();
+    let mut moved = BTreeMap::new();
+    for old_key in &old_only {
+        let candidates: Vec<&str> = new_only
+            .iter()
+            .copied()
+            .filter(|new_key| same_type(old_key, new_key))
+            .collect();
+        if let [new_key] = candidates[..] {
+            if old_only.iter().filter(|k| same_type(k, new_key)).count() == 1 {
+                moved.insert(*old_key, new_key);
+            }
+        }
+    }
+    moved
+}
+
+/// The scanned source of a project, ready to be checked or queried.
+pub struct Analyzer {
+    config: Config,
+    visitor: SynVisitor,
+}
+
+impl Analyzer {
+    /// Scan all `.rs` files under the configured source directories.
+    ///
+    /// Enums marked `#[migration_root]` in the source are added to the
+    /// configured roots. When no roots are configured, the default
+    /// `KeyValue` root is then only kept if such an enum exists, and
+    /// `Config::output` goes to the first root.
+    pub fn new(mut config: Config) -> Result<Self> {
+        let mut visitor = SynVisitor::new(&config);
+        visitor.walk_dir()?;
+        let configured = !config.roots.is_empty();
+        if !visitor.declared_roots().is_empty() {
+            if !configured && visitor.has_root(DEFAULT_ROOT) {
+                config.roots.push(Root::new(DEFAULT_ROOT));
+            }
+            config
+                .roots
+                .extend(visitor.declared_roots().iter().map(Root::new));
+        }
+        if let Some(output) = config.output.take().filter(|_| !configured) {
+            if config.roots.is_empty() {
+                config.roots.push(Root::new(DEFAULT_ROOT));
+            }
+            config.roots[0].schema = Some(output);
+        }
+        Ok(Analyzer { config, visitor })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Fingerprints and structure of all serialized types reachable from
+    /// any store root or standalone store type.
+    pub fn schema(&self) -> Schema {
+        self.visitor.construct_schema(None, true)
+    }
+
+    /// Fingerprints and structure of the types reachable from `root`.
+    /// Standalone store types (`#[store_type]`) belong to the first root.
+    pub fn root_schema(&self, root: &Root) -> Schema {
+        let standalone = self.config.roots().first() == Some(root);
+        self.visitor.construct_schema(Some(&root.name), standalone)
+    }
+
+    /// Run all checks, comparing each root against its schema file. Unless
+    /// `update` is set, a fingerprint that differs from the stored one is
+    /// reported as a change. The schema files are rewritten only when the
+    /// report passes.
+    pub fn check(&self, update: bool) -> Result<CheckReport> {
+        let mut report = CheckReport {
+            diagnostics: self.visitor.diagnostics().to_vec(),
+            ..Default::default()
+        };
+        report
+            .diagnostics
+            .extend(self.visitor.check_store_types_in_types_dir());
+        report
+            .diagnostics
+            .extend(self.visitor.check_store_types_deserializable());
+
+        for root in self.config.roots() {
+            let schema_path = self.config.schema_path(&root);
+            let schema = self.root_schema(&root);
+            if !update {
+                let old_schema = Schema::load(self.config.baseline_schema_path(&root))?;
+                let changes = if old_schema.has_legacy_fingerprints() {
+                    // Compare like with like; a passing check upgrades the file
+                    let legacy = Schema {
+                        fingerprints: self.visitor.legacy_fingerprints(&schema.fingerprints),
+                        ..schema.clone()
+                    };
+                    self.compare_schemas(&old_schema, &legacy)
+                } else {
+                    self.compare_schemas(&old_schema, &schema)
+                };
+                report.diagnostics.extend(changes);
+            }
+            report.roots.push(RootReport {
+                root: root.name,
+                schema_path,
+                schema,
+            });
+        }
+
+        for diagnostic in &mut report.diagnostics {
+            diagnostic.severity = self.config.severity_for(diagnostic);
+        }
+
+        if report.passed() {
+            for root in &report.roots {
+                root.schema.write(&root.schema_path)?;
+            }
+            report.dumped = true;
+        }
+        Ok(report)
+    }
+
+    /// The schema of the source under each of `cfg_sets`, e.g. one per
+    /// feature combination, flagging every store type whose fingerprint
+    /// differs between them or that is only persisted under some.
+    pub fn check_matrix(config: &Config, cfg_sets: &[CfgSet]) -> Result<MatrixReport> {
+        let mut analyzers = vec![];
+        for cfg in cfg_sets {
+            let config = Config {
+                cfg: Some(cfg.clone()),
+                ..config.clone()
+            };
+            analyzers.push(Analyzer::new(config)?);
+        }
+        let schemas: Vec<FeatureSchema> = analyzers
+            .iter()
+            .zip(cfg_sets)
+            .map(|(analyzer, cfg)| FeatureSchema {
+                cfg: cfg.to_string(),
+                schema: analyzer.schema(),
+            })
+            .collect();
+
+        let mut report = MatrixReport::default();
+        let type_names: BTreeSet<&String> = schemas
+            .iter()
+            .flat_map(|schema| schema.schema.fingerprints.keys())
+            .collect();
+        for type_name in type_names {
+            let fingerprints: BTreeMap<String, Option<String>> = schemas
+                .iter()
+                .map(|schema| {
+                    let finger = schema.schema.fingerprints.get(type_name).cloned();
+                    (schema.cfg.clone(), finger)
+                })
+                .collect();
+            let layouts: BTreeSet<&Option<String>> = fingerprints.values().collect();
+            if layouts.len() > 1 {
+                let span = analyzers
+                    .iter()
+                    .find_map(|analyzer| analyzer.visitor.type_span(type_name))
+                    .cloned();
+                let mut diagnostic = Diagnostic::new(
+                    DiagnosticKind::FeatureDependentLayout {
+                        type_name: type_name.clone(),
+                        fingerprints,
+                    },
+                    span,
+                );
+                diagnostic.severity = config.severity_for(&diagnostic);
+                report.diagnostics.push(diagnostic);
+            }
+        }
+        report.schemas = schemas;
+        Ok(report)
+    }
+
+    /// One diagnostic per type whose fingerprint differs between `old`
+    /// and `new`, or that is only present in one of them.
+    fn compare_schemas(&self, old: &Schema, new: &Schema) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        // Against an empty (first run) schema every type would be "added"
+        let track_membership = !old.fingerprints.is_empty();
+        let moved = moved_keys(old, new);
+        for (old_name, old_finger) in old.fingerprints.iter() {
+            let type_name = moved.get(old_name.as_str()).copied().unwrap_or(old_name);
+            let Some(new_finger) = new.fingerprints.get(type_name) else {
+                if track_membership {
+                    diagnostics.push(Diagnostic::new(
+                        DiagnosticKind::StoreTypeRemoved {
+                            type_name: type_name.to_string(),
+                            still_defined: self.visitor.is_defined(type_name),
+                        },
+                        self.visitor.type_span(type_name).cloned(),
+                    ));
+                }
+                continue;
+            };
+            if old_finger == new_finger {
+                continue;
+            }
+            let (old_schema, new_schema) = (old.types.get(old_name), new.types.get(type_name));
+            let mut changes = match (old_schema, new_schema) {
+                (Some(old), Some(new)) => {
+                    classify_changes(self.config.wire_format, old, new, diff_types(old, new))
+                }
+                _ => vec![],
+            };
+            // Aliases are folded into the fingerprints of the types using them
+            for alias in self.visitor.folded_aliases(type_name) {
+                if let (
+                    Some(TypeSchema::Alias { target: old_target }),
+                    Some(TypeSchema::Alias { target: new_target }),
+                    Some(old_schema),
+                    Some(new_schema),
+                ) = (
+                    old.types.get(&alias),
+                    new.types.get(&alias),
+                    old_schema,
+                    new_schema,
+                ) {
+                    if old_target != new_target {
+                        let change = Change::AliasChanged {
+                            alias,
+                            old: old_target.clone(),
+                            new: new_target.clone(),
+                        };
+                        changes.extend(classify_changes(
+                            self.config.wire_format,
+                            old_schema,
+                            new_schema,
+                            vec![change],
+                        ));
+                    }
+                }
+            }
+            // Without a structural diff we cannot tell what broke
+            let breaking = changes.is_empty()
+                || changes.iter().any(|c| {
+                    c.compatibility
+                        .is_none_or(|c| c == Compatibility::NeedsMigration)
+                });
+            let mut diagnostic = Diagnostic::new(
+                DiagnosticKind::FingerprintChanged {
+                    type_name: type_name.to_string(),
+                    old: old_finger.clone(),
+                    new: new_finger.clone(),
+                    changes,
+                    chains: self.visitor.try_find_type_chain(type_name, false),
+                },
+                self.visitor.type_span(type_name).cloned(),
+            );
+            if !breaking {
+                diagnostic.severity = Severity::Warning;
+            }
+            diagnostics.push(diagnostic);
+        }
+        if track_membership {
+            let moved: BTreeSet<&str> = moved.into_values().collect();
+            for type_name in new.fingerprints.keys() {
+                if !old.fingerprints.contains_key(type_name) && !moved.contains(type_name.as_str())
+                {
+                    diagnostics.push(Diagnostic::new(
+                        DiagnosticKind::StoreTypeAdded {
+                            type_name: type_name.clone(),
+                            chains: self.visitor.try_find_type_chain(type_name, false),
+                        },
+                        self.visitor.type_span(type_name).cloned(),
+                    ));
+                }
+            }
+        }
+        diagnostics
+    }
+
+    /// Whether `type_name` is reachable from a store root, and through which
+    /// chains. Fails with a `TypeNotFound` diagnostic for unknown types.
+    pub fn query_type(&self, type_name: &str) -> std::result::Result<TypeQuery, Box<Diagnostic>> {
+        self.visitor.query_type(type_name)
+    }
+
+    /// All defined types, split into store-related and not store-related.
+    pub fn list_types(&self) -> TypeListing {
+        self.visitor.list_types()
+    }
+}
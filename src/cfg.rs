@@ -0,0 +1,218 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Expr, Fields, Item, Lit, Meta, Token};
+
+/// The active cfg options of a build, e.g. `test` and `feature = "serde"`,
+/// against which `#[cfg(...)]` and `#[cfg_attr(...)]` are evaluated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    /// `(name, None)` for `test`, `(name, Some(value))` for
+    /// `feature = "serde"`.
+    options: BTreeSet<(String, Option<String>)>,
+}
+
+impl CfgSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable an option given like rustc's `--cfg`: `name` or
+    /// `name="value"`.
+    pub fn enable(&mut self, option: &str) {
+        let option = match option.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_string(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (option.trim().to_string(), None),
+        };
+        self.options.insert(option);
+    }
+
+    /// Enable `feature = "<feature>"`.
+    pub fn enable_feature(&mut self, feature: &str) {
+        self.options
+            .insert(("feature".to_string(), Some(feature.trim().to_string())));
+    }
+
+    /// Whether the cfg predicate `predicate`, like `feature = "serde"` or
+    /// `all(unix, not(test))`, holds.
+    pub fn is_active(&self, predicate: &Meta) -> bool {
+        match predicate {
+            Meta::Path(path) => path
+                .get_ident()
+                .is_some_and(|name| self.options.contains(&(name.to_string(), None))),
+            Meta::NameValue(name_value) => match (name_value.path.get_ident(), &name_value.value) {
+                (
+                    Some(name),
+                    Expr::Lit(syn::ExprLit {
+                        lit: Lit::Str(value),
+                        ..
+                    }),
+                ) => self
+                    .options
+                    .contains(&(name.to_string(), Some(value.value()))),
+                _ => false,
+            },
+            Meta::List(list) => {
+                let args = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .unwrap_or_default();
+                if list.path.is_ident("all") {
+                    args.iter().all(|arg| self.is_active(arg))
+                } else if list.path.is_ident("any") {
+                    args.iter().any(|arg| self.is_active(arg))
+                } else if list.path.is_ident("not") {
+                    args.len() == 1 && !self.is_active(&args[0])
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Whether every `#[cfg(...)]` in `attrs` holds. Predicates that fail
+    /// to parse are taken to hold, so their item is still scanned.
+    pub fn is_enabled(&self, attrs: &[Attribute]) -> bool {
+        attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cfg"))
+            .all(|attr| {
+                attr.parse_args::<Meta>()
+                    .map_or(true, |predicate| self.is_active(&predicate))
+            })
+    }
+
+    /// Replace each active `#[cfg_attr(predicate, attrs..)]` by `attrs`.
+    /// Inactive ones are kept as they are, so that markers like
+    /// `#[cfg_attr(any(), store_type)]` are still seen.
+    pub fn expand_cfg_attrs(&self, attrs: &mut Vec<Attribute>) {
+        let mut expanded = Vec::with_capacity(attrs.len());
+        let mut pending: Vec<Attribute> = std::mem::take(attrs);
+        pending.reverse();
+        while let Some(attr) = pending.pop() {
+            let args = match &attr.meta {
+                Meta::List(list) if attr.path().is_ident("cfg_attr") => list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .ok(),
+                _ => None,
+            };
+            match args {
+                Some(args) if args.first().is_some_and(|p| self.is_active(p)) => {
+                    // Nested `cfg_attr`s are expanded in turn
+                    for meta in args.into_iter().skip(1).rev() {
+                        pending.push(Attribute {
+                            meta,
+                            ..attr.clone()
+                        });
+                    }
+                }
+                _ => expanded.push(attr),
+            }
+        }
+        *attrs = expanded;
+    }
+
+    /// Drop the items, fields, variants and impl items of `file` that are
+    /// behind an inactive `#[cfg]`, and expand active `cfg_attr`s.
+    pub fn configure(&self, file: &mut syn::File) {
+        self.configure_items(&mut file.items);
+    }
+
+    /// See `configure`. Returns false if the attributes disable their item.
+    fn configure_attrs(&self, attrs: &mut Vec<Attribute>) -> bool {
+        self.expand_cfg_attrs(attrs);
+        self.is_enabled(attrs)
+    }
+
+    fn configure_items(&self, items: &mut Vec<Item>) {
+        items.retain_mut(|item| {
+            if !item_attrs(item).is_none_or(|attrs| self.configure_attrs(attrs)) {
+                return false;
+            }
+            match item {
+                Item::Struct(item_struct) => self.configure_fields(&mut item_struct.fields),
+                Item::Enum(item_enum) => retain(&mut item_enum.variants, |variant| {
+                    self.configure_fields(&mut variant.fields);
+                    self.configure_attrs(&mut variant.attrs)
+                }),
+                Item::Mod(item_mod) => {
+                    if let Some((_, items)) = &mut item_mod.content {
+                        self.configure_items(items);
+                    }
+                }
+                Item::Impl(item_impl) => item_impl.items.retain_mut(|item| match item {
+                    syn::ImplItem::Const(item) => self.configure_attrs(&mut item.attrs),
+                    syn::ImplItem::Fn(item) => self.configure_attrs(&mut item.attrs),
+                    syn::ImplItem::Type(item) => self.configure_attrs(&mut item.attrs),
+                    syn::ImplItem::Macro(item) => self.configure_attrs(&mut item.attrs),
+                    _ => true,
+                }),
+                _ => {}
+            }
+            true
+        });
+    }
+
+    fn configure_fields(&self, fields: &mut Fields) {
+        match fields {
+            Fields::Named(named) => retain(&mut named.named, |field| {
+                self.configure_attrs(&mut field.attrs)
+            }),
+            Fields::Unnamed(unnamed) => retain(&mut unnamed.unnamed, |field| {
+                self.configure_attrs(&mut field.attrs)
+            }),
+            Fields::Unit => {}
+        }
+    }
+}
+
+/// The attributes of `item`, if it can have any.
+fn item_attrs(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    match item {
+        Item::Const(item) => Some(&mut item.attrs),
+        Item::Enum(item) => Some(&mut item.attrs),
+        Item::ExternCrate(item) => Some(&mut item.attrs),
+        Item::Fn(item) => Some(&mut item.attrs),
+        Item::ForeignMod(item) => Some(&mut item.attrs),
+        Item::Impl(item) => Some(&mut item.attrs),
+        Item::Macro(item) => Some(&mut item.attrs),
+        Item::Mod(item) => Some(&mut item.attrs),
+        Item::Static(item) => Some(&mut item.attrs),
+        Item::Struct(item) => Some(&mut item.attrs),
+        Item::Trait(item) => Some(&mut item.attrs),
+        Item::TraitAlias(item) => Some(&mut item.attrs),
+        Item::Type(item) => Some(&mut item.attrs),
+        Item::Union(item) => Some(&mut item.attrs),
+        Item::Use(item) => Some(&mut item.attrs),
+        _ => None,
+    }
+}
+
+/// Keep the elements of `list` for which `keep` returns true.
+fn retain<T, P: Default>(list: &mut Punctuated<T, P>, mut keep: impl FnMut(&mut T) -> bool) {
+    *list = std::mem::take(list)
+        .into_iter()
+        .filter_map(|mut item| keep(&mut item).then_some(item))
+        .collect();
+}
+
+/// Options as rustc prints them with `--print cfg`, e.g.
+/// `feature="serde", test`, or `default` when none are enabled.
+impl fmt::Display for CfgSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.options.is_empty() {
+            return write!(f, "default");
+        }
+        let options: Vec<String> = self
+            .options
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{}=\"{}\"", name, value),
+                None => name.clone(),
+            })
+            .collect();
+        write!(f, "{}", options.join(", "))
+    }
+}
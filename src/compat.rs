@@ -0,0 +1,351 @@
+use crate::diff::Change;
+use crate::schema::{FieldSchema, TypeSchema, VariantSchema};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// The encoding the store uses for its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// bincode 1.x: struct fields are positional and enum variants are
+    /// encoded by index; nothing is length-prefixed.
+    Bincode,
+    /// serde_json: fields and variants are encoded by name, unknown fields
+    /// are ignored.
+    Json,
+    /// rmp-serde's default encoding: structs are length-prefixed arrays and
+    /// enum variants are encoded by name.
+    MessagePack,
+}
+
+impl WireFormat {
+    fn fields_by_name(self) -> bool {
+        self == WireFormat::Json
+    }
+
+    fn variants_by_name(self) -> bool {
+        self != WireFormat::Bincode
+    }
+
+    /// Whether a positional struct records its length, so that a reader
+    /// can fill in missing trailing fields from `#[serde(default)]`.
+    fn length_prefixed(self) -> bool {
+        self == WireFormat::MessagePack
+    }
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Bincode => write!(f, "bincode"),
+            WireFormat::Json => write!(f, "json"),
+            WireFormat::MessagePack => write!(f, "messagepack"),
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(WireFormat::Bincode),
+            "json" => Ok(WireFormat::Json),
+            "messagepack" | "msgpack" => Ok(WireFormat::MessagePack),
+            _ => Err(format!(
+                "unknown wire format `{}`, expected one of: bincode, json, messagepack",
+                s
+            )),
+        }
+    }
+}
+
+/// How a change affects data already in the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compatibility {
+    /// Old and new code can read each other's data.
+    Compatible,
+    /// New code can read old data, but data written by new code cannot be
+    /// read after a rollback.
+    ForwardOnly,
+    /// New code cannot read old data without a migration.
+    NeedsMigration,
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compatibility::Compatible => write!(f, "compatible"),
+            Compatibility::ForwardOnly => write!(f, "forward-only"),
+            Compatibility::NeedsMigration => write!(f, "needs migration"),
+        }
+    }
+}
+
+/// A structural change, labeled for a wire format when one is configured.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClassifiedChange {
+    #[serde(flatten)]
+    pub change: Change,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compatibility: Option<Compatibility>,
+}
+
+impl fmt::Display for ClassifiedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.change)?;
+        if let Some(compatibility) = self.compatibility {
+            write!(f, " ({})", compatibility)?;
+        }
+        Ok(())
+    }
+}
+
+/// Label every change from `old` to `new` for `format`.
+pub fn classify_changes(
+    format: Option<WireFormat>,
+    old: &TypeSchema,
+    new: &TypeSchema,
+    changes: Vec<Change>,
+) -> Vec<ClassifiedChange> {
+    changes
+        .into_iter()
+        .map(|change| ClassifiedChange {
+            compatibility: format.map(|format| classify(format, old, new, &change)),
+            change,
+        })
+        .collect()
+}
+
+/// Classify a single change. `old` and `new` are the two versions of the
+/// type the change was computed from.
+pub fn classify(
+    format: WireFormat,
+    old: &TypeSchema,
+    new: &TypeSchema,
+    change: &Change,
+) -> Compatibility {
+    use Compatibility::*;
+    match change {
+        Change::KindChanged { .. } | Change::CustomImplChanged { .. } => NeedsMigration,
+        Change::SerdeChanged {
+            old: old_serde,
+            new: new_serde,
+        } => {
+            // An enum's `rename` and `rename_all` name its variants
+            let names_ignored = match new {
+                TypeSchema::Enum { .. } => !format.variants_by_name(),
+                _ => !format.fields_by_name(),
+            };
+            serde_change(old_serde, new_serde, names_ignored)
+        }
+        Change::FieldAdded {
+            variant,
+            position,
+            field,
+        } => {
+            if format.fields_by_name() {
+                if has_default(field, true) {
+                    Compatible
+                } else {
+                    NeedsMigration
+                }
+            } else if format.length_prefixed()
+                && *position >= field_count(old, variant)
+                && has_default(field, false)
+            {
+                ForwardOnly
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::FieldRemoved { field, .. } => {
+            if !format.fields_by_name() {
+                NeedsMigration
+            } else if has_default(field, true) {
+                Compatible
+            } else {
+                ForwardOnly
+            }
+        }
+        Change::FieldRenamed {
+            variant,
+            position,
+            old: old_name,
+            ..
+        } => {
+            let old_wire = fields(old, variant)
+                .iter()
+                .find(|f| f.name.as_deref() == Some(old_name.as_str()))
+                .and_then(FieldSchema::wire_name)
+                .unwrap_or(old_name);
+            let new_field = field(new, variant, *position);
+            if !format.fields_by_name()
+                || new_field.and_then(FieldSchema::wire_name) == Some(old_wire)
+            {
+                Compatible
+            } else if new_field.is_some_and(|f| has_alias(&f.serde, old_wire)) {
+                ForwardOnly
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::FieldTypeChanged { old, new, .. }
+        | Change::AliasTargetChanged { old, new }
+        | Change::AliasChanged { old, new, .. } => {
+            if format != WireFormat::Bincode && is_integer_widening(old, new) {
+                ForwardOnly
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::FieldSerdeChanged { old, new, .. } => {
+            serde_change(old, new, !format.fields_by_name())
+        }
+        Change::FieldReordered { .. } => {
+            if format.fields_by_name() {
+                Compatible
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::VariantAdded { position, .. } => {
+            if *position >= variant_count(old) || (format.variants_by_name() && repr(new).is_none())
+            {
+                ForwardOnly
+            } else {
+                // Inserting in the middle shifts the index and the implicit
+                // discriminants of later variants
+                NeedsMigration
+            }
+        }
+        Change::VariantRemoved { .. } => NeedsMigration,
+        Change::VariantRenamed {
+            position,
+            old: old_name,
+            ..
+        } => {
+            let old_wire = variants(old)
+                .iter()
+                .find(|v| &v.name == old_name)
+                .map_or(old_name.as_str(), VariantSchema::wire_name);
+            let new_variant = variants(new).get(*position);
+            if !format.variants_by_name() || new_variant.is_some_and(|v| v.wire_name() == old_wire)
+            {
+                Compatible
+            } else if new_variant.is_some_and(|v| has_alias(&v.serde, old_wire)) {
+                ForwardOnly
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::VariantReordered { .. } => {
+            // Implicit discriminants follow the declaration order
+            if format.variants_by_name() && repr(new).is_none() {
+                Compatible
+            } else {
+                NeedsMigration
+            }
+        }
+        Change::VariantSerdeChanged { old, new, .. } => {
+            serde_change(old, new, !format.variants_by_name())
+        }
+        // Positional and `repr`-based encoders write the discriminant
+        Change::ReprChanged { .. } | Change::DiscriminantChanged { .. } => NeedsMigration,
+    }
+}
+
+fn fields<'a>(schema: &'a TypeSchema, variant: &Option<String>) -> &'a [FieldSchema] {
+    match (schema, variant) {
+        (TypeSchema::Struct { fields, .. }, None) => fields,
+        (TypeSchema::Enum { variants, .. }, Some(name)) => variants
+            .iter()
+            .find(|v| &v.name == name)
+            .map(|v| v.fields.as_slice())
+            .unwrap_or_default(),
+        _ => &[],
+    }
+}
+
+fn field<'a>(
+    schema: &'a TypeSchema,
+    variant: &Option<String>,
+    position: usize,
+) -> Option<&'a FieldSchema> {
+    fields(schema, variant).get(position)
+}
+
+fn field_count(schema: &TypeSchema, variant: &Option<String>) -> usize {
+    fields(schema, variant).len()
+}
+
+fn variants(schema: &TypeSchema) -> &[VariantSchema] {
+    match schema {
+        TypeSchema::Enum { variants, .. } => variants,
+        TypeSchema::Struct { .. } | TypeSchema::Alias { .. } => &[],
+    }
+}
+
+fn variant_count(schema: &TypeSchema) -> usize {
+    variants(schema).len()
+}
+
+fn repr(schema: &TypeSchema) -> Option<&str> {
+    match schema {
+        TypeSchema::Enum { repr, .. } => repr.as_deref(),
+        TypeSchema::Struct { .. } | TypeSchema::Alias { .. } => None,
+    }
+}
+
+/// The directive name, e.g. `rename` for `rename = "a"`.
+fn directive_key(directive: &str) -> &str {
+    directive.split('=').next().unwrap_or_default().trim()
+}
+
+/// Whether a missing value for `field` can be filled in on read. Named
+/// formats also treat a missing `Option` as `None`.
+fn has_default(field: &FieldSchema, by_name: bool) -> bool {
+    field.serde.iter().any(|d| directive_key(d) == "default")
+        || (by_name && field.ty.starts_with("Option <"))
+}
+
+fn has_alias(serde: &[String], name: &str) -> bool {
+    serde
+        .iter()
+        .any(|d| directive_key(d) == "alias" && d.contains(&format!("\"{}\"", name)))
+}
+
+/// Directive changes that only affect names, defaults or aliases do not
+/// change the data; renames only matter when names are encoded.
+fn serde_change(old: &[String], new: &[String], names_ignored: bool) -> Compatibility {
+    let old: BTreeSet<&String> = old.iter().collect();
+    let new: BTreeSet<&String> = new.iter().collect();
+    let harmless = |directive: &&String| {
+        let key = directive_key(directive);
+        key == "default" || key == "alias" || (names_ignored && key.starts_with("rename"))
+    };
+    if old.symmetric_difference(&new).all(harmless) {
+        Compatibility::Compatible
+    } else {
+        Compatibility::NeedsMigration
+    }
+}
+
+/// `u32 -> u64` and the like: every old value is representable in the new
+/// type, so self-sized integer encodings keep working.
+fn is_integer_widening(old: &str, new: &str) -> bool {
+    const UNSIGNED: &[&str] = &["u8", "u16", "u32", "u64", "u128"];
+    const SIGNED: &[&str] = &["i8", "i16", "i32", "i64", "i128"];
+    [UNSIGNED, SIGNED].iter().any(|ladder| {
+        match (
+            ladder.iter().position(|t| *t == old),
+            ladder.iter().position(|t| *t == new),
+        ) {
+            (Some(o), Some(n)) => o < n,
+            _ => false,
+        }
+    })
+}
@@ -0,0 +1,184 @@
+use crate::analyzer::{Config, Root, RpcRules};
+use crate::cfg::CfgSet;
+use crate::error::{Error, Result};
+use crate::paths::{PathRole, PathRule, PathRules};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The name of the project configuration file, see [`Config::load`].
+pub const CONFIG_FILE_NAME: &str = "migration-check.toml";
+
+/// A `migration-check.toml`. Every key is optional:
+///
+/// ```toml
+/// source-dirs = ["crates/node/src", "crates/types/src"]
+/// types-dir = "crates/types/src"
+/// roots = ["KeyValue", { name = "PeerColumn", schema = "peer.schema.json" }]
+/// wire-format = "bincode"
+/// parse-mode = "strict"
+/// features = ["watchtower"]
+/// external-types = ["u64", "String", "Vec", "PeerId", "vendor::Pubkey"]
+///
+/// [paths]
+/// include = ["**/*.rs"]
+/// exclude = ["**/tests/**"]
+/// rules = ["store=**/gen/keep/**"]
+/// gitignore = true
+///
+/// [rpc]
+/// paths = ["api/**", "**/crates/rpc/src/**"]
+/// hex-types = ["u64", "u128"]
+///
+/// [severity]
+/// store-type-added = "warning"
+/// MC0006 = "error"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    source_dirs: Vec<PathBuf>,
+    types_dir: Option<PathBuf>,
+    roots: Vec<RootEntry>,
+    wire_format: Option<String>,
+    parse_mode: Option<String>,
+    cfg: Vec<String>,
+    features: Vec<String>,
+    external_types: Option<Vec<String>>,
+    paths: PathsSection,
+    rpc: RpcSection,
+    severity: BTreeMap<String, String>,
+}
+
+/// A root as `"KeyValue"` or `{ name = "KeyValue", schema = "..." }`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RootEntry {
+    Name(String),
+    Table(RootTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RootTable {
+    name: String,
+    schema: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PathsSection {
+    include: Vec<String>,
+    /// Skipped even if the RPC paths or a rule match them.
+    exclude: Vec<String>,
+    /// `ROLE=GLOB` rules, applied after the RPC paths.
+    rules: Vec<String>,
+    gitignore: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct RpcSection {
+    paths: Vec<String>,
+    hex_types: Option<Vec<String>>,
+}
+
+impl Config {
+    /// The `migration-check.toml` in `dir` or in its closest ancestor that
+    /// has one.
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    /// Read the configuration file at `path`. Source directories, the
+    /// types dir and schema files are relative to the directory of the
+    /// file; path patterns are matched as in [`PathRules`].
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |message: String| Error::Config {
+            path: path.to_path_buf(),
+            message,
+        };
+        let file: ConfigFile = toml::from_str(&text).map_err(|err| invalid(err.to_string()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let resolve = |relative: &Path| base.join(relative).to_string_lossy().into_owned();
+        let roots = file
+            .roots
+            .into_iter()
+            .map(|root| match root {
+                RootEntry::Name(name) => Root::new(name),
+                RootEntry::Table(table) => Root {
+                    name: table.name,
+                    schema: table.schema.map(|schema| base.join(schema)),
+                },
+            })
+            .collect();
+
+        let mut paths = PathRules::default();
+        paths.include = file.paths.include;
+        paths.exclude = file.paths.exclude;
+        paths.rules.extend(
+            file.rpc
+                .paths
+                .into_iter()
+                .map(|pattern| PathRule::new(PathRole::Rpc, pattern)),
+        );
+        for rule in &file.paths.rules {
+            paths.rules.push(rule.parse().map_err(invalid)?);
+        }
+        if let Some(gitignore) = file.paths.gitignore {
+            paths.gitignore = gitignore;
+        }
+
+        let cfg = (!file.cfg.is_empty() || !file.features.is_empty()).then(|| {
+            let mut cfg = CfgSet::new();
+            for option in &file.cfg {
+                cfg.enable(option);
+            }
+            for feature in &file.features {
+                cfg.enable_feature(feature);
+            }
+            cfg
+        });
+        let mut severities = BTreeMap::new();
+        for (name, severity) in file.severity {
+            severities.insert(name, severity.parse().map_err(invalid)?);
+        }
+
+        Ok(Config {
+            source_dirs: file.source_dirs.iter().map(|dir| resolve(dir)).collect(),
+            types_dir: file.types_dir.as_deref().map(resolve),
+            roots,
+            output: None,
+            wire_format: file
+                .wire_format
+                .map(|format| format.parse())
+                .transpose()
+                .map_err(invalid)?,
+            severities,
+            cfg,
+            parse_mode: file
+                .parse_mode
+                .map(|mode| mode.parse())
+                .transpose()
+                .map_err(invalid)?
+                .unwrap_or_default(),
+            paths,
+            external_types: file
+                .external_types
+                .unwrap_or_else(|| Config::default().external_types),
+            rpc: RpcRules {
+                hex_types: file
+                    .rpc
+                    .hex_types
+                    .unwrap_or_else(|| RpcRules::default().hex_types),
+            },
+        })
+    }
+}
@@ -0,0 +1,312 @@
+use crate::compat::ClassifiedChange;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!(
+                "unknown severity `{}`, expected `error` or `warning`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A position in a scanned source file. Lines and columns are 1-based and
+/// absent when only the file is known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub file: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Span {
+    pub fn file(file: impl Into<String>) -> Self {
+        Span {
+            file: file.into(),
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a [`Diagnostic`] is about. Each kind has a stable code so that
+/// tooling can tell failures apart without matching on messages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind")]
+pub enum DiagnosticKind {
+    /// A numeric field in an RPC type is missing its hex `serde_as` attribute.
+    RpcMissingHexSerde {
+        /// The struct or enum containing the field.
+        type_name: String,
+        field_name: String,
+        /// e.g. `U64Hex` or `Option<U64Hex>`
+        expected: String,
+    },
+    /// A store-reachable type is defined outside of the configured types-dir.
+    StoreTypeOutsideTypesDir {
+        type_name: String,
+        types_dir: String,
+        /// Dependency chains from a store root, like `KeyValue::Variant -> Type -> Target`.
+        chains: Vec<String>,
+    },
+    /// A store type's fingerprint differs from the one in the schema file.
+    FingerprintChanged {
+        type_name: String,
+        old: String,
+        new: String,
+        /// What changed structurally, labeled for the configured wire format.
+        /// Empty when the old schema file has no structure for the type
+        /// (legacy format).
+        changes: Vec<ClassifiedChange>,
+        chains: Vec<String>,
+    },
+    /// A queried type is not defined in the scanned source directories.
+    TypeNotFound {
+        type_name: String,
+        dirs: Vec<String>,
+    },
+    /// A source file could not be parsed, so its types were not analyzed.
+    ParseFailure { message: String },
+    /// A type in the schema file is no longer reachable from its store root. Data
+    /// of this type may be orphaned in the store.
+    StoreTypeRemoved {
+        type_name: String,
+        /// Whether the type is still defined in the scanned source.
+        still_defined: bool,
+    },
+    /// A type reachable from a store root is not in the schema file yet. It is
+    /// newly persisted and should be reviewed.
+    StoreTypeAdded {
+        type_name: String,
+        chains: Vec<String>,
+    },
+    /// A serialized store type neither derives nor implements `Deserialize`,
+    /// so data of this type cannot be read back.
+    StoreTypeNotDeserializable {
+        type_name: String,
+        chains: Vec<String>,
+    },
+    /// A store type's layout differs between cfg sets, so data written by a
+    /// build with some features cannot be read by a build with others.
+    FeatureDependentLayout {
+        type_name: String,
+        /// Fingerprint per cfg set; `None` where the type is not persisted.
+        fingerprints: BTreeMap<String, Option<String>>,
+    },
+    /// A queried name matches types in several modules.
+    AmbiguousType {
+        type_name: String,
+        /// Full paths of the matching types, like `store::Config`.
+        candidates: Vec<String>,
+    },
+}
+
+impl DiagnosticKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::RpcMissingHexSerde { .. } => "MC0001",
+            DiagnosticKind::StoreTypeOutsideTypesDir { .. } => "MC0002",
+            DiagnosticKind::FingerprintChanged { .. } => "MC0003",
+            DiagnosticKind::TypeNotFound { .. } => "MC0004",
+            DiagnosticKind::ParseFailure { .. } => "MC0005",
+            DiagnosticKind::StoreTypeRemoved { .. } => "MC0006",
+            DiagnosticKind::StoreTypeAdded { .. } => "MC0007",
+            DiagnosticKind::AmbiguousType { .. } => "MC0008",
+            DiagnosticKind::StoreTypeNotDeserializable { .. } => "MC0009",
+            DiagnosticKind::FeatureDependentLayout { .. } => "MC0010",
+        }
+    }
+
+    /// A short, stable name for the kind, e.g. `rpc-missing-hex-serde`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::RpcMissingHexSerde { .. } => "rpc-missing-hex-serde",
+            DiagnosticKind::StoreTypeOutsideTypesDir { .. } => "store-type-outside-types-dir",
+            DiagnosticKind::FingerprintChanged { .. } => "fingerprint-changed",
+            DiagnosticKind::TypeNotFound { .. } => "type-not-found",
+            DiagnosticKind::ParseFailure { .. } => "parse-failure",
+            DiagnosticKind::StoreTypeRemoved { .. } => "store-type-removed",
+            DiagnosticKind::StoreTypeAdded { .. } => "store-type-added",
+            DiagnosticKind::AmbiguousType { .. } => "ambiguous-type",
+            DiagnosticKind::StoreTypeNotDeserializable { .. } => "store-type-not-deserializable",
+            DiagnosticKind::FeatureDependentLayout { .. } => "feature-dependent-layout",
+        }
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            DiagnosticKind::ParseFailure { .. } | DiagnosticKind::StoreTypeAdded { .. } => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// A single finding about the scanned source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub span: Option<Span>,
+    #[serde(flatten)]
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, span: Option<Span>) -> Self {
+        Diagnostic {
+            code: kind.code(),
+            severity: kind.default_severity(),
+            span,
+            kind,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// One-line description of the finding.
+    pub fn message(&self) -> String {
+        match &self.kind {
+            DiagnosticKind::RpcMissingHexSerde {
+                type_name,
+                field_name,
+                expected,
+            } => format!(
+                "field `{}` of `{}` expected serde_as: {}, but you missed it",
+                field_name, type_name, expected
+            ),
+            DiagnosticKind::StoreTypeOutsideTypesDir {
+                type_name,
+                types_dir,
+                ..
+            } => format!(
+                "store type `{}` is NOT defined in types-dir ({})",
+                type_name, types_dir
+            ),
+            DiagnosticKind::FingerprintChanged {
+                type_name,
+                old,
+                new,
+                ..
+            } => format!("type fingerprint changed: {} {} -> {}", type_name, old, new),
+            DiagnosticKind::TypeNotFound { type_name, .. } => format!(
+                "type `{}` was not found in the scanned source directories",
+                type_name
+            ),
+            DiagnosticKind::ParseFailure { message } => {
+                format!("failed to parse file: {}", message)
+            }
+            DiagnosticKind::StoreTypeRemoved { type_name, .. } => format!(
+                "store type `{}` was removed from the schema; its data may be orphaned",
+                type_name
+            ),
+            DiagnosticKind::StoreTypeAdded { type_name, .. } => {
+                format!("new store type `{}` is now persisted", type_name)
+            }
+            DiagnosticKind::StoreTypeNotDeserializable { type_name, .. } => format!(
+                "store type `{}` is serialized but cannot be deserialized",
+                type_name
+            ),
+            DiagnosticKind::FeatureDependentLayout { type_name, .. } => format!(
+                "persisted layout of `{}` depends on the enabled features",
+                type_name
+            ),
+            DiagnosticKind::AmbiguousType { type_name, .. } => format!(
+                "type `{}` is defined in several modules, use its full path",
+                type_name
+            ),
+        }
+    }
+
+    /// Supporting lines printed below the message.
+    pub fn notes(&self) -> Vec<String> {
+        match &self.kind {
+            DiagnosticKind::StoreTypeOutsideTypesDir { chains, .. }
+            | DiagnosticKind::StoreTypeAdded { chains, .. }
+            | DiagnosticKind::StoreTypeNotDeserializable { chains, .. } => chains
+                .iter()
+                .map(|chain| format!("dependency chain: {}", chain))
+                .collect(),
+            DiagnosticKind::FingerprintChanged {
+                changes, chains, ..
+            } => changes
+                .iter()
+                .map(|change| format!("change: {}", change))
+                .chain(
+                    chains
+                        .iter()
+                        .map(|chain| format!("dependency chain: {}", chain)),
+                )
+                .collect(),
+            DiagnosticKind::TypeNotFound { dirs, .. } => {
+                vec![format!("scanned directories: {:?}", dirs)]
+            }
+            DiagnosticKind::FeatureDependentLayout { fingerprints, .. } => fingerprints
+                .iter()
+                .map(|(cfg, finger)| match finger {
+                    Some(finger) => format!("with {}: {}", cfg, finger),
+                    None => format!("with {}: not persisted", cfg),
+                })
+                .collect(),
+            DiagnosticKind::AmbiguousType { candidates, .. } => candidates
+                .iter()
+                .map(|candidate| format!("candidate: {}", candidate))
+                .collect(),
+            DiagnosticKind::StoreTypeRemoved {
+                still_defined: true,
+                ..
+            } => {
+                vec![
+                    "the type is still defined but no longer reachable from the store root"
+                        .to_string(),
+                ]
+            }
+            DiagnosticKind::StoreTypeRemoved {
+                still_defined: false,
+                ..
+            } => vec!["the type is no longer defined in the scanned source".to_string()],
+            _ => vec![],
+        }
+    }
+}
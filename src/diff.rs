@@ -0,0 +1,581 @@
+use crate::schema::{FieldSchema, TypeSchema, VariantSchema};
+use serde::Serialize;
+use std::fmt;
+
+/// One structural difference between two versions of a type. Positions are
+/// 0-based declaration indexes, i.e. the order positional encoders use.
+/// `variant` is set when the field belongs to an enum variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// The type changed between struct and enum.
+    KindChanged {
+        old: String,
+        new: String,
+    },
+    /// The container `#[serde(...)]` directives changed.
+    SerdeChanged {
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// The hand-written `Serialize` or `Deserialize` impls changed, were
+    /// added or were removed. Holds the hashes of the impls.
+    CustomImplChanged {
+        old: Option<String>,
+        new: Option<String>,
+    },
+    FieldAdded {
+        variant: Option<String>,
+        position: usize,
+        field: FieldSchema,
+    },
+    FieldRemoved {
+        variant: Option<String>,
+        position: usize,
+        field: FieldSchema,
+    },
+    /// A field got a new name in Rust. Either it kept its wire name, or it
+    /// is at the same position with the same type and other directives.
+    FieldRenamed {
+        variant: Option<String>,
+        position: usize,
+        old: String,
+        new: String,
+    },
+    FieldTypeChanged {
+        variant: Option<String>,
+        position: usize,
+        name: Option<String>,
+        old: String,
+        new: String,
+    },
+    FieldSerdeChanged {
+        variant: Option<String>,
+        position: usize,
+        name: Option<String>,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    FieldReordered {
+        variant: Option<String>,
+        name: String,
+        old_position: usize,
+        new_position: usize,
+    },
+    VariantAdded {
+        position: usize,
+        name: String,
+    },
+    VariantRemoved {
+        position: usize,
+        name: String,
+    },
+    /// A variant got a new name in Rust. Either it kept its wire name, or
+    /// it is at the same position with the same fields.
+    VariantRenamed {
+        position: usize,
+        old: String,
+        new: String,
+    },
+    VariantReordered {
+        name: String,
+        old_position: usize,
+        new_position: usize,
+    },
+    VariantSerdeChanged {
+        name: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A type alias now refers to another type.
+    AliasTargetChanged {
+        old: String,
+        new: String,
+    },
+    /// A type alias the type refers to, directly or through other aliases,
+    /// now refers to another type. The alias is reported on its own too.
+    AliasChanged {
+        alias: String,
+        old: String,
+        new: String,
+    },
+    /// The enum's `#[repr(...)]` changed.
+    ReprChanged {
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A variant's explicit discriminant changed, was added or was removed.
+    DiscriminantChanged {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+/// `` `3` `` for an explicit value, `implicit` otherwise.
+fn describe_value(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("`{}`", value),
+        None => "implicit".to_string(),
+    }
+}
+
+/// `field `a`` or `field 2` for tuple fields, prefixed by the variant.
+fn describe_field(variant: &Option<String>, name: Option<&str>, position: usize) -> String {
+    let field = match name {
+        Some(name) => format!("field `{}`", name),
+        None => format!("field {}", position),
+    };
+    match variant {
+        Some(variant) => format!("variant `{}` {}", variant, field),
+        None => field,
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::KindChanged { old, new } => write!(f, "changed from {} to {}", old, new),
+            Change::SerdeChanged { old, new } => {
+                write!(f, "serde attributes changed from {:?} to {:?}", old, new)
+            }
+            Change::CustomImplChanged { old: None, .. } => {
+                write!(f, "hand-written serde impl added")
+            }
+            Change::CustomImplChanged { new: None, .. } => {
+                write!(f, "hand-written serde impl removed")
+            }
+            Change::CustomImplChanged { .. } => write!(f, "hand-written serde impl changed"),
+            Change::FieldAdded {
+                variant,
+                position,
+                field,
+            } => write!(
+                f,
+                "{} added at position {}: `{}`",
+                describe_field(variant, field.name.as_deref(), *position),
+                position,
+                field.ty
+            ),
+            Change::FieldRemoved {
+                variant,
+                position,
+                field,
+            } => write!(
+                f,
+                "{} removed from position {}: `{}`",
+                describe_field(variant, field.name.as_deref(), *position),
+                position,
+                field.ty
+            ),
+            Change::FieldRenamed {
+                variant,
+                position,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} renamed to `{}`",
+                describe_field(variant, Some(old), *position),
+                new
+            ),
+            Change::FieldTypeChanged {
+                variant,
+                position,
+                name,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} type changed from `{}` to `{}`",
+                describe_field(variant, name.as_deref(), *position),
+                old,
+                new
+            ),
+            Change::FieldSerdeChanged {
+                variant,
+                position,
+                name,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} serde attributes changed from {:?} to {:?}",
+                describe_field(variant, name.as_deref(), *position),
+                old,
+                new
+            ),
+            Change::FieldReordered {
+                variant,
+                name,
+                old_position,
+                new_position,
+            } => write!(
+                f,
+                "{} moved from position {} to {}",
+                describe_field(variant, Some(name), *old_position),
+                old_position,
+                new_position
+            ),
+            Change::VariantAdded { position, name } => {
+                write!(f, "variant `{}` added at position {}", name, position)
+            }
+            Change::VariantRemoved { position, name } => {
+                write!(f, "variant `{}` removed from position {}", name, position)
+            }
+            Change::VariantRenamed { old, new, .. } => {
+                write!(f, "variant `{}` renamed to `{}`", old, new)
+            }
+            Change::VariantReordered {
+                name,
+                old_position,
+                new_position,
+            } => write!(
+                f,
+                "variant `{}` moved from position {} to {}",
+                name, old_position, new_position
+            ),
+            Change::VariantSerdeChanged { name, old, new } => write!(
+                f,
+                "variant `{}` serde attributes changed from {:?} to {:?}",
+                name, old, new
+            ),
+            Change::AliasTargetChanged { old, new } => {
+                write!(f, "alias target changed from `{}` to `{}`", old, new)
+            }
+            Change::AliasChanged { alias, old, new } => {
+                write!(f, "alias `{}` changed from `{}` to `{}`", alias, old, new)
+            }
+            Change::ReprChanged { old, new } => write!(
+                f,
+                "repr changed from {} to {}",
+                describe_value(old),
+                describe_value(new)
+            ),
+            Change::DiscriminantChanged { name, old, new } => write!(
+                f,
+                "variant `{}` discriminant changed from {} to {}",
+                name,
+                describe_value(old),
+                describe_value(new)
+            ),
+        }
+    }
+}
+
+/// Compute the structural differences from `old` to `new`.
+pub fn diff_types(old: &TypeSchema, new: &TypeSchema) -> Vec<Change> {
+    let mut changes = vec![];
+    if old.serde() != new.serde() {
+        changes.push(Change::SerdeChanged {
+            old: old.serde().to_vec(),
+            new: new.serde().to_vec(),
+        });
+    }
+    if old.custom_impl() != new.custom_impl() {
+        changes.push(Change::CustomImplChanged {
+            old: old.custom_impl().map(str::to_string),
+            new: new.custom_impl().map(str::to_string),
+        });
+    }
+    match (old, new) {
+        (TypeSchema::Struct { fields: old, .. }, TypeSchema::Struct { fields: new, .. }) => {
+            changes.extend(diff_fields(&None, old, new))
+        }
+        (
+            TypeSchema::Enum {
+                variants: old,
+                repr: old_repr,
+                ..
+            },
+            TypeSchema::Enum {
+                variants: new,
+                repr: new_repr,
+                ..
+            },
+        ) => {
+            if old_repr != new_repr {
+                changes.push(Change::ReprChanged {
+                    old: old_repr.clone(),
+                    new: new_repr.clone(),
+                });
+            }
+            changes.extend(diff_variants(old, new))
+        }
+        (TypeSchema::Alias { target: old }, TypeSchema::Alias { target: new }) => {
+            if old != new {
+                changes.push(Change::AliasTargetChanged {
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+        _ => {
+            return vec![Change::KindChanged {
+                old: kind_name(old).to_string(),
+                new: kind_name(new).to_string(),
+            }]
+        }
+    }
+    changes
+}
+
+fn kind_name(schema: &TypeSchema) -> &'static str {
+    match schema {
+        TypeSchema::Struct { .. } => "struct",
+        TypeSchema::Enum { .. } => "enum",
+        TypeSchema::Alias { .. } => "alias",
+    }
+}
+
+/// Whether `directive` only names the item, like `rename = "a"` or
+/// `alias = "b"`.
+fn is_name_directive(directive: &str) -> bool {
+    let key = directive.split('=').next().unwrap_or_default().trim();
+    key == "rename" || key == "alias"
+}
+
+/// The directives of `serde` that are not about the item's name. For items
+/// with the same wire name, these are the ones that can change the data.
+fn without_names(serde: &[String]) -> Vec<String> {
+    serde
+        .iter()
+        .filter(|directive| !is_name_directive(directive))
+        .cloned()
+        .collect()
+}
+
+/// Items that can be matched by name across two versions of a list.
+trait Named {
+    /// The wire name, see `FieldSchema::wire_name`.
+    fn name(&self) -> Option<&str>;
+    /// Whether `other` has the same content apart from the names, which
+    /// makes an add/remove pair at the same position a rename.
+    fn same_shape(&self, other: &Self) -> bool;
+}
+
+impl Named for FieldSchema {
+    fn name(&self) -> Option<&str> {
+        self.wire_name()
+    }
+
+    fn same_shape(&self, other: &Self) -> bool {
+        self.ty == other.ty && without_names(&self.serde) == without_names(&other.serde)
+    }
+}
+
+impl Named for VariantSchema {
+    fn name(&self) -> Option<&str> {
+        Some(self.wire_name())
+    }
+
+    fn same_shape(&self, other: &Self) -> bool {
+        self.fields == other.fields && self.discriminant == other.discriminant
+    }
+}
+
+/// How the items of an old and a new list correspond.
+struct Matching {
+    /// (old position, new position) of items present in both.
+    common: Vec<(usize, usize)>,
+    /// (old position, new position) of items that were renamed.
+    renamed: Vec<(usize, usize)>,
+    removed: Vec<usize>,
+    added: Vec<usize>,
+}
+
+/// Match items by wire name, or by position when they are unnamed (tuple
+/// fields). An unmatched pair at the same position with the same shape is
+/// treated as a rename.
+fn match_items<T: Named>(old: &[T], new: &[T]) -> Matching {
+    let mut matching = Matching {
+        common: vec![],
+        renamed: vec![],
+        removed: vec![],
+        added: vec![],
+    };
+    let mut new_matched = vec![false; new.len()];
+    let mut old_unmatched = vec![];
+    for (i, item) in old.iter().enumerate() {
+        let found = match item.name() {
+            Some(name) => new.iter().position(|n| n.name() == Some(name)),
+            None => (i < new.len() && new[i].name().is_none()).then_some(i),
+        };
+        match found {
+            Some(j) => {
+                new_matched[j] = true;
+                matching.common.push((i, j));
+            }
+            None => old_unmatched.push(i),
+        }
+    }
+    for i in old_unmatched {
+        if i < new.len() && !new_matched[i] && old[i].same_shape(&new[i]) {
+            new_matched[i] = true;
+            matching.renamed.push((i, i));
+        } else {
+            matching.removed.push(i);
+        }
+    }
+    matching.added = (0..new.len()).filter(|j| !new_matched[*j]).collect();
+    matching
+}
+
+/// Items in `common` whose order relative to the other common items changed.
+fn reordered(common: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut by_new: Vec<(usize, usize)> = common.to_vec();
+    by_new.sort_by_key(|(_, j)| *j);
+    common
+        .iter()
+        .enumerate()
+        .filter(|(rank, pair)| by_new[*rank] != **pair)
+        .map(|(_, pair)| *pair)
+        .collect()
+}
+
+fn diff_fields(variant: &Option<String>, old: &[FieldSchema], new: &[FieldSchema]) -> Vec<Change> {
+    let matching = match_items(old, new);
+    let mut changes = vec![];
+    for &i in &matching.removed {
+        changes.push(Change::FieldRemoved {
+            variant: variant.clone(),
+            position: i,
+            field: old[i].clone(),
+        });
+    }
+    for &j in &matching.added {
+        changes.push(Change::FieldAdded {
+            variant: variant.clone(),
+            position: j,
+            field: new[j].clone(),
+        });
+    }
+    for &(i, j) in &matching.renamed {
+        if old[i].name == new[j].name {
+            // Only the `rename` changed
+            changes.push(Change::FieldSerdeChanged {
+                variant: variant.clone(),
+                position: j,
+                name: new[j].name.clone(),
+                old: old[i].serde.clone(),
+                new: new[j].serde.clone(),
+            });
+        } else {
+            changes.push(Change::FieldRenamed {
+                variant: variant.clone(),
+                position: j,
+                old: old[i].name.clone().unwrap_or_default(),
+                new: new[j].name.clone().unwrap_or_default(),
+            });
+        }
+    }
+    for &(i, j) in &matching.common {
+        if old[i].name != new[j].name {
+            changes.push(Change::FieldRenamed {
+                variant: variant.clone(),
+                position: j,
+                old: old[i].name.clone().unwrap_or_default(),
+                new: new[j].name.clone().unwrap_or_default(),
+            });
+        }
+        if old[i].ty != new[j].ty {
+            changes.push(Change::FieldTypeChanged {
+                variant: variant.clone(),
+                position: j,
+                name: new[j].name.clone(),
+                old: old[i].ty.clone(),
+                new: new[j].ty.clone(),
+            });
+        }
+        // The wire name is the same, so only the other directives matter
+        let (old_serde, new_serde) = (without_names(&old[i].serde), without_names(&new[j].serde));
+        if old_serde != new_serde {
+            changes.push(Change::FieldSerdeChanged {
+                variant: variant.clone(),
+                position: j,
+                name: new[j].name.clone(),
+                old: old_serde,
+                new: new_serde,
+            });
+        }
+    }
+    for (i, j) in reordered(&matching.common) {
+        changes.push(Change::FieldReordered {
+            variant: variant.clone(),
+            name: new[j].name.clone().unwrap_or_default(),
+            old_position: i,
+            new_position: j,
+        });
+    }
+    changes
+}
+
+fn diff_variants(old: &[VariantSchema], new: &[VariantSchema]) -> Vec<Change> {
+    let matching = match_items(old, new);
+    let mut changes = vec![];
+    for &i in &matching.removed {
+        changes.push(Change::VariantRemoved {
+            position: i,
+            name: old[i].name.clone(),
+        });
+    }
+    for &j in &matching.added {
+        changes.push(Change::VariantAdded {
+            position: j,
+            name: new[j].name.clone(),
+        });
+    }
+    for &(i, j) in &matching.renamed {
+        if old[i].name == new[j].name {
+            // Only the `rename` changed
+            changes.push(Change::VariantSerdeChanged {
+                name: new[j].name.clone(),
+                old: old[i].serde.clone(),
+                new: new[j].serde.clone(),
+            });
+        } else {
+            changes.push(Change::VariantRenamed {
+                position: j,
+                old: old[i].name.clone(),
+                new: new[j].name.clone(),
+            });
+        }
+    }
+    for &(i, j) in &matching.common {
+        if old[i].name != new[j].name {
+            changes.push(Change::VariantRenamed {
+                position: j,
+                old: old[i].name.clone(),
+                new: new[j].name.clone(),
+            });
+        }
+        if old[i].discriminant != new[j].discriminant {
+            changes.push(Change::DiscriminantChanged {
+                name: new[j].name.clone(),
+                old: old[i].discriminant.clone(),
+                new: new[j].discriminant.clone(),
+            });
+        }
+        let (old_serde, new_serde) = (without_names(&old[i].serde), without_names(&new[j].serde));
+        if old_serde != new_serde {
+            changes.push(Change::VariantSerdeChanged {
+                name: new[j].name.clone(),
+                old: old_serde,
+                new: new_serde,
+            });
+        }
+        changes.extend(diff_fields(
+            &Some(new[j].name.clone()),
+            &old[i].fields,
+            &new[j].fields,
+        ));
+    }
+    for (i, j) in reordered(&matching.common) {
+        changes.push(Change::VariantReordered {
+            name: new[j].name.clone(),
+            old_position: i,
+            new_position: j,
+        });
+    }
+    changes
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that prevent the analysis from producing a report at all.
+/// Findings about the scanned code (RPC fields, types-dir violations,
+/// fingerprint changes) are not errors; they are returned as
+/// [`Diagnostic`](crate::Diagnostic)s in the reports.
+#[derive(Debug)]
+pub enum Error {
+    /// A source or schema file could not be read or written.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The existing schema file is not valid JSON.
+    Schema {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The configuration file is not valid.
+    Config { path: PathBuf, message: String },
+    /// A path rule is not a valid glob.
+    Glob {
+        pattern: String,
+        source: globset::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Schema { path, source } => {
+                write!(f, "invalid schema file {}: {}", path.display(), source)
+            }
+            Error::Config { path, message } => {
+                write!(f, "invalid config file {}: {}", path.display(), message)
+            }
+            Error::Glob { pattern, source } => {
+                write!(f, "invalid path pattern `{}`: {}", pattern, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Schema { source, .. } => Some(source),
+            Error::Config { .. } => None,
+            Error::Glob { source, .. } => Some(source),
+        }
+    }
+}
@@ -1,2 +1,65 @@
+//! Detect changes to the layout of types persisted in a store.
+//!
+//! The store is modeled by one or more root enums, `KeyValue` by default:
+//! every type reachable from their variants through serialized fields is
+//! fingerprinted, and the fingerprints are compared against a [`Schema`]
+//! file checked into the repository.
+//!
+//! Roots can also be declared in code, along with types that are persisted
+//! on their own rather than through a root enum. `cfg_attr(any(), ..)`
+//! keeps the markers out of the compiled code:
+//!
+//! ```ignore
+//! #[cfg_attr(any(), migration_root)]
+//! pub enum PeerColumn { Peer(PeerId, PeerInfo) }
+//!
+//! #[cfg_attr(any(), store_type)]
+//! #[derive(Serialize, Deserialize)]
+//! pub struct NodeAnnouncementBlob { /* .. */ }
+//! ```
+//!
+//! Options can be pinned in a `migration-check.toml` at the root of the
+//! repository, see [`Config::load`].
+//!
+//! ```no_run
+//! use migration_check::{Analyzer, Config};
+//!
+//! let config = Config::new(vec!["src".to_string()]);
+//! let analyzer = Analyzer::new(config)?;
+//! let report = analyzer.check(false)?;
+//! assert!(report.passed());
+//! # Ok::<(), migration_check::Error>(())
+//! ```
+
+mod analyzer;
+mod cfg;
+mod compat;
+mod config_file;
+mod diagnostic;
+mod diff;
+mod error;
+mod paths;
+mod render;
+mod report;
+mod schema;
+mod visitor;
+
+pub use analyzer::{
+    Analyzer, Config, ParseMode, Root, RpcRules, DEFAULT_EXTERNAL_TYPES, DEFAULT_ROOT,
+};
+pub use cfg::CfgSet;
+pub use compat::{classify, classify_changes, ClassifiedChange, Compatibility, WireFormat};
+pub use config_file::CONFIG_FILE_NAME;
+pub use diagnostic::{Diagnostic, DiagnosticKind, Severity, Span};
+pub use diff::{diff_types, Change};
+pub use error::{Error, Result};
+pub use paths::{PathRole, PathRule, PathRules};
+pub use render::{render, render_human, render_json, render_sarif, Format};
+pub use report::{
+    CheckReport, FeatureSchema, Fingerprints, MatrixReport, RootReport, TypeListing, TypeLocation,
+    TypeQuery,
+};
+pub use schema::{FieldSchema, Schema, TypeSchema, VariantSchema, SCHEMA_VERSION};
+
 #[cfg(test)]
 mod tests;
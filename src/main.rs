@@ -1,966 +1,441 @@
 use clap::Parser;
-use proc_macro2::TokenTree;
-use sha2::{Digest, Sha256};
-use std::collections::BTreeMap;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use migration_check::{
+    render, render_human, Analyzer, CfgSet, CheckReport, Config, DiagnosticKind, Format,
+    MatrixReport, ParseMode, PathRule, Root, Severity, TypeListing, TypeLocation, TypeQuery,
+    WireFormat, CONFIG_FILE_NAME,
+};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use syn::visit::Visit;
-use syn::Type;
-use syn::{Fields, ItemStruct};
-use walkdir::WalkDir;
-
-/// Well-known primitive types and external crate types that are not expected
-/// to be defined in the scanned source directories. These are excluded from
-/// the "store types must live in types-dir" check.
-const BUILTIN_TYPES: &[&str] = &[
-    "u8",
-    "u16",
-    "u32",
-    "u64",
-    "u128",
-    "i8",
-    "i16",
-    "i32",
-    "i64",
-    "i128",
-    "f32",
-    "f64",
-    "bool",
-    "String",
-    "str",
-    "usize",
-    "isize",
-    "Option",
-    "Vec",
-    "HashMap",
-    "HashSet",
-    "BTreeMap",
-    "BTreeSet",
-    "Box",
-    "Arc",
-    "Rc",
-    "Cow",
-    "PhantomData",
-    "Duration",
-    // External crate types commonly seen in KeyValue
-    "PeerId",
-    "OutPoint",
-];
-
-pub struct SynVisitor {
-    types: Vec<String>,
-    type_fingerprint: HashMap<String, String>,
-    type_deps: HashMap<String, Vec<String>>,
-    store_types: Vec<String>,
-    /// KeyValue variant info: (variant_name, dep_types) for each variant.
-    /// Used to produce meaningful dep chains like `KeyValue::Variant -> Type -> Target`.
-    store_variants: Vec<(String, Vec<String>)>,
-    /// All source directories to scan
-    dirs: Vec<String>,
-    /// Optional: the types-dir path prefix. Types defined in files under this
-    /// directory are considered "in the types crate".
-    types_dir: Option<String>,
-    /// Records which file each type was first defined in.
-    type_file: HashMap<String, String>,
-    /// Types that derive `Serialize` via `#[derive(Serialize)]`.
-    /// For these types, we know ALL non-skipped fields are serialized, so we
-    /// follow their field deps in the types-dir check.
-    derive_serializable_types: HashSet<String>,
-    /// Types that have a custom `impl Serialize for T`.
-    /// For these, we can't determine which fields are serialized from syntax
-    /// alone, so we include them in the check but DON'T follow their field deps.
-    custom_serializable_types: HashSet<String>,
-    in_rpc: bool,
-    has_error: bool,
-    current_file: String,
-}
-
-impl SynVisitor {
-    pub fn new(dirs: Vec<String>, types_dir: Option<String>) -> Self {
-        SynVisitor {
-            types: Vec::new(),
-            type_fingerprint: HashMap::new(),
-            type_deps: HashMap::new(),
-            store_types: Vec::new(),
-            store_variants: Vec::new(),
-            dirs,
-            types_dir,
-            type_file: HashMap::new(),
-            derive_serializable_types: HashSet::new(),
-            custom_serializable_types: HashSet::new(),
-            in_rpc: false,
-            has_error: false,
-            current_file: String::new(),
-        }
-    }
-
-    fn calc_dep_types(&self, ty: Type) -> Vec<String> {
-        let mut dep_types = vec![];
-        match ty {
-            Type::Path(type_path) => {
-                for elem in quote::quote! { #type_path } {
-                    match elem {
-                        TokenTree::Ident(ident) => {
-                            dep_types.push(format!("{}", quote::quote! { #ident }));
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            Type::Tuple(type_tuple) => {
-                for elem in &type_tuple.elems {
-                    dep_types.extend(self.calc_dep_types(elem.clone()));
-                }
-            }
-            _ => {}
-        }
-        dep_types
-    }
 
-    /// Check if the item attributes include `#[derive(Serialize, ...)]`.
-    fn has_serialize_derive(attrs: &[syn::Attribute]) -> bool {
-        attrs.iter().any(|attr| {
-            if !attr.path().is_ident("derive") {
-                return false;
-            }
-            if let syn::Meta::List(meta_list) = &attr.meta {
-                let tokens_str = meta_list.tokens.to_string();
-                // Check for "Serialize" as a standalone token in the derive list
-                tokens_str.split(',').any(|part| part.trim() == "Serialize")
-            } else {
-                false
-            }
-        })
-    }
+#[derive(Parser)]
+#[command(author, version, about = "Schema migration checking tool")]
+struct Cli {
+    /// Configuration file. Defaults to the `migration-check.toml` of the
+    /// working directory or of its closest ancestor that has one. Options
+    /// given on the command line override its settings.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Source code directories to scan (can be specified multiple times).
+    /// Required unless set as `source-dirs` in the configuration file.
+    #[clap(short, long, num_args = 1..)]
+    source_code_dir: Vec<String>,
 
-    /// Returns true if the field should be excluded from the fingerprint and
-    /// dependency graph. This includes:
-    /// - `#[serde(skip)]`, `#[serde(skip_serializing)]`, `#[serde(skip_deserializing)]`
-    /// - `#[skip_store]` - explicit annotation for migration-check
-    /// - `#[cfg_attr(any(), skip_store)]` - conditional skip_store annotation
-    fn should_skip_field(&self, field: &syn::Field) -> bool {
-        field.attrs.iter().any(|attr| {
-            let attr_name = attr.path().segments.last().unwrap().ident.to_string();
-
-            // Check for #[skip_store] attribute
-            if attr_name == "skip_store" {
-                return true;
-            }
+    /// Output file path. Only valid with a single store root; use
+    /// `--root NAME=SCHEMA` to place the schema files of several roots.
+    /// Without --root, it is the schema file of `KeyValue`, or of the first
+    /// `#[migration_root]` enum if there is no `KeyValue`.
+    #[clap(short, long)]
+    output: Option<String>,
 
-            // Check for #[cfg_attr(any(), skip_store)] pattern
-            if attr_name == "cfg_attr" {
-                let tokens = self.get_attr_tokens(attr);
-                // Check if tokens contain "skip_store"
-                if tokens.contains("skip_store") {
-                    return true;
-                }
-            }
+    /// Store root enum, as NAME or NAME=SCHEMA, where NAME is an enum name
+    /// like `KeyValue` or a path like `store::KeyValue`. Each root is
+    /// checked against its own schema file (can be specified multiple
+    /// times, defaults to `KeyValue`). Enums marked
+    /// `#[cfg_attr(any(), migration_root)]` are roots as well.
+    #[clap(long = "root", value_parser = parse_root)]
+    roots: Vec<Root>,
 
-            // Check for serde skip attributes
-            if attr_name == "serde" {
-                let tokens = self.get_attr_tokens(attr);
-                // Split by comma to check each individual directive.
-                // e.g. #[serde(skip, default)] → tokens = "skip , default"
-                return tokens.split(',').any(|part| {
-                    let trimmed = part.trim();
-                    trimmed == "skip"
-                        || trimmed == "skip_serializing"
-                        || trimmed == "skip_deserializing"
-                });
-            }
+    /// Types crate source directory. When specified, the tool will check that
+    /// all store-reachable types (from any store root) are defined within
+    /// this directory and error if any are found outside it.
+    #[clap(short, long)]
+    types_dir: Option<String>,
 
-            false
-        })
-    }
+    /// Force update fingerprint
+    #[arg(short = 'u', long, default_value_t = false)]
+    update: bool,
 
-    // check if the field is a number and has the serde_as attribute
-    // with the expected value
-    // e.g. #[serde_as(as = "Option<u8>")]
-    // or #[serde_as(as = "u8")]
-    fn check_rpc_field(&mut self, struct_name: &str, field: &syn::Field) {
-        let ty = field.ty.clone();
-        let dep_types = self.calc_dep_types(ty);
-        if dep_types.len() > 2 {
-            return;
-        }
-        let Some(last) = dep_types.last() else {
-            return;
-        };
-        if field.ident.is_none() {
-            return;
-        }
-        if !(last == "u8" || last == "u16" || last == "u32" || last == "u64" || last == "u128") {
-            return;
-        }
-        let is_option = dep_types.len() == 2 && dep_types[0] == "Option";
+    /// Query a type's store dependency chain. If the type is reachable from
+    /// a store root (i.e., it is persisted to the store), prints the dependency
+    /// chain(s). Otherwise prints that the type is not related to the store.
+    #[clap(short, long)]
+    query_type: Option<String>,
 
-        let serde_attrs = field
-            .attrs
-            .iter()
-            .filter(|attr| attr.path().is_ident("serde_as") || attr.path().is_ident("serde"))
-            .collect::<Vec<_>>();
-        let expected_hex = format!("{}Hex", last.to_uppercase());
-        let expected_serde_as_value = if is_option {
-            format!("Option<{}>", expected_hex)
-        } else {
-            expected_hex
-        };
+    /// List all types defined in the scanned source that are NOT related to
+    /// the store (i.e., not reachable from any store root). Useful for identifying
+    /// types that can be safely moved without migration concerns.
+    #[clap(long, default_value_t = false)]
+    list_non_store_types: bool,
 
-        if !serde_attrs.iter().any(|attr| {
-            let attr_str = self.get_attr_tokens(attr);
-            if let Some(attr_value) = attr_str.split('=').nth(1) {
-                attr_value.contains(&expected_serde_as_value)
-            } else {
-                false
-            }
-        }) {
-            eprintln!(
-                "File: {} struct/enum: {} field_name: {:?} expected serde_as: {}, but you missed it",
-                self.current_file, struct_name, field.ident, expected_serde_as_value
-            );
-            self.has_error = true;
-        }
-    }
+    /// Output format: human, json or sarif. Machine-readable formats are
+    /// written to stdout; json also applies to --query-type and
+    /// --list-non-store-types.
+    #[clap(long, default_value = "human")]
+    format: Format,
+
+    /// Encoding of the store values: bincode, json or messagepack. When set,
+    /// fingerprint changes are classified for it, and only changes that
+    /// need a migration fail the check.
+    #[clap(long)]
+    wire_format: Option<WireFormat>,
+
+    /// Override the severity of a diagnostic kind, as NAME=error or
+    /// NAME=warning, where NAME is a kind like `store-type-removed` or a
+    /// code like `MC0006` (can be specified multiple times)
+    #[clap(long = "severity", value_parser = parse_severity)]
+    severities: Vec<(String, Severity)>,
+
+    /// Evaluate `#[cfg]` and `cfg_attr` with this option enabled, as NAME
+    /// or NAME="VALUE" (can be specified multiple times). Without --cfg or
+    /// --features, items are scanned regardless of their cfg.
+    #[clap(long = "cfg")]
+    cfgs: Vec<String>,
+
+    /// Features to enable when evaluating `#[cfg]`, comma-separated (can be
+    /// specified multiple times)
+    #[clap(long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Compute the schema once per feature set, each a comma-separated list
+    /// of features that may be empty, and flag store types whose layout
+    /// differs between them (can be specified multiple times). With
+    /// --format json, prints every schema.
+    #[clap(long = "feature-matrix")]
+    feature_matrix: Vec<String>,
+
+    /// What to do with source files that fail to parse or are not UTF-8:
+    /// skip them with a warning, fail the check (strict), or analyze every
+    /// item that parses (recover) [default: skip]
+    #[clap(long)]
+    parse_mode: Option<ParseMode>,
+
+    /// Only scan the files matching this glob (can be specified multiple
+    /// times). Globs match paths relative to the source directory as well
+    /// as paths as found, and `*` does not match `/`.
+    #[clap(long)]
+    include: Vec<String>,
+
+    /// Skip the files matching this glob, even if a --path rule matches
+    /// them (can be specified multiple times)
+    #[clap(long)]
+    exclude: Vec<String>,
+
+    /// Scan the files matching a glob as store code, RPC code or not at
+    /// all, as ROLE=GLOB where ROLE is store, rpc or ignore. The last
+    /// matching rule wins, after the defaults
+    /// `rpc=**/rpc/**`, `ignore=**/gen/**` and `ignore=**/migrations/**`
+    /// (can be specified multiple times)
+    #[clap(long = "path")]
+    path_rules: Vec<PathRule>,
+
+    /// Also scan the files ignored by .gitignore
+    #[clap(long, default_value_t = false)]
+    no_gitignore: bool,
+}
 
-    fn get_attr_tokens(&self, attr: &syn::Attribute) -> String {
-        match &attr.meta {
-            syn::Meta::List(meta_list) => meta_list.tokens.to_string(),
-            syn::Meta::NameValue(_meta_name_value) => "".to_string(),
-            _ => String::new(),
-        }
-    }
+fn parse_severity(s: &str) -> Result<(String, Severity), String> {
+    let (name, severity) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=SEVERITY, got `{}`", s))?;
+    Ok((name.to_string(), severity.parse()?))
+}
 
-    /// Returns true if the current file is under a `/rpc/` directory.
-    /// Types defined there are RPC-specific wrappers and should not shadow
-    /// the canonical definitions in the types crate.
-    fn is_rpc_file(&self) -> bool {
-        self.current_file.contains("/rpc/")
+fn parse_root(s: &str) -> Result<Root, String> {
+    match s.split_once('=') {
+        Some((name, schema)) => Ok(Root {
+            name: name.to_string(),
+            schema: Some(schema.into()),
+        }),
+        None => Ok(Root::new(s)),
     }
+}
 
-    /// Record the file where a type is defined, preferring non-RPC locations.
-    /// If the type was previously recorded from an RPC file and we now see it
-    /// in a non-RPC file, overwrite the record.
-    fn record_type_file(&mut self, type_name: &str) {
-        if self.is_rpc_file() {
-            // Only insert if this type has never been seen before
-            self.type_file
-                .entry(type_name.to_string())
-                .or_insert_with(|| self.current_file.clone());
-        } else {
-            // Non-RPC file always takes priority — overwrite any previous entry
-            self.type_file
-                .insert(type_name.to_string(), self.current_file.clone());
+/// Print the schema size per feature set and the types whose layout
+/// depends on features.
+fn print_matrix(report: &MatrixReport, cli: &Cli) {
+    match cli.format {
+        Format::Json => print_json(report),
+        Format::Sarif => println!("{}", render(cli.format, &report.diagnostics)),
+        Format::Human => {
+            for schema in &report.schemas {
+                eprintln!(
+                    "with {}: {} store types",
+                    schema.cfg,
+                    schema.schema.fingerprints.len()
+                );
+            }
+            eprint!("{}", render_human(&report.diagnostics));
         }
     }
+}
 
-    fn inner_visit_item_struct(&mut self, item_struct: &ItemStruct) {
-        let struct_name = item_struct.ident.to_string();
-        self.types.push(struct_name.clone());
-        self.record_type_file(&struct_name);
-
-        if Self::has_serialize_derive(&item_struct.attrs) {
-            self.derive_serializable_types.insert(struct_name.clone());
-        }
-
-        let mut fingerprint = String::new();
-
-        fingerprint.push_str(&format!("struct_name:{}\n", struct_name));
-
-        let mut dep_types = vec![];
-        match &item_struct.fields {
-            Fields::Named(fields) => {
-                for field in &fields.named {
-                    if self.in_rpc {
-                        // RPC check runs on all fields (regardless of serde attrs)
-                        self.check_rpc_field(&struct_name, field);
-                    } else {
-                        // For fingerprint/deps, skip fields with #[serde(skip)]
-                        if self.should_skip_field(field) {
-                            continue;
-                        }
-                        let field_type = quote::quote! { #field.ty }.to_string();
-                        let field_type = field_type.split(":").last().unwrap_or_default();
-                        fingerprint.push_str(&format!("field: {}\n", field_type));
-                        dep_types.extend(self.calc_dep_types(field.ty.clone()));
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        if !self.in_rpc {
-            let mut hasher = Sha256::new();
-            hasher.update(fingerprint.as_bytes());
-            let finger_hash = format!("{:x}", hasher.finalize());
-            self.type_fingerprint
-                .insert(struct_name.clone(), finger_hash.clone());
-            self.add_type_deps(&struct_name, dep_types.clone());
-        }
-    }
+/// Print `value` as JSON to stdout, for the machine-readable formats.
+fn print_json(value: &impl serde::Serialize) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
 
-    fn inner_visit_item_enum(&mut self, item_enum: &'_ syn::ItemEnum) {
-        let enum_name = item_enum.ident.to_string();
-        let mut dep_types = vec![];
-        self.types.push(enum_name.clone());
-        self.record_type_file(&enum_name);
+fn print_query(query: &TypeQuery) {
+    let type_name = &query.type_name;
+    if query.store_related {
+        println!(
+            "Type `{}` is STORE-RELATED (reachable from a store root).",
+            type_name
+        );
+        println!();
 
-        if Self::has_serialize_derive(&item_enum.attrs) {
-            self.derive_serializable_types.insert(enum_name.clone());
+        if let Some(span) = &query.span {
+            println!("Defined in: {}", span);
         }
 
-        let is_key_value = enum_name == "KeyValue";
-
-        let mut fingerprint = String::new();
-        fingerprint.push_str(&format!("enum_name:{}\n", enum_name));
-
-        for variant in &item_enum.variants {
-            let variant_name = variant.ident.to_string();
-            fingerprint.push_str(&format!("variant:{}\n", variant_name));
-
-            let mut variant_dep_types = vec![];
-            for field in &variant.fields {
-                if self.in_rpc {
-                    // RPC check runs on all fields (regardless of serde attrs)
-                    self.check_rpc_field(&enum_name, field);
-                } else {
-                    // For fingerprint/deps, skip fields with #[serde(skip)]
-                    if self.should_skip_field(field) {
-                        continue;
-                    }
-                    let field_type = quote::quote! { #field.ty }.to_string();
-                    fingerprint.push_str(&format!("field:{}\n", field_type));
-                    variant_dep_types.extend(self.calc_dep_types(field.ty.clone()));
-                }
-            }
-
-            if is_key_value && !self.in_rpc {
-                self.store_variants
-                    .push((variant_name, variant_dep_types.clone()));
+        if query.chains.is_empty() {
+            println!("  (direct store root variant type)");
+        } else {
+            println!("Dependency chain(s) from store roots:");
+            for chain in &query.chains {
+                println!("  {}", chain);
             }
-            dep_types.extend(variant_dep_types);
         }
 
-        if !self.in_rpc {
-            let mut hasher = Sha256::new();
-            hasher.update(fingerprint.as_bytes());
-            let finger_hash = format!("{:x}", hasher.finalize());
-            self.type_fingerprint.insert(enum_name.clone(), finger_hash);
-            self.add_type_deps(&enum_name, dep_types.clone());
-            if is_key_value {
-                self.store_types = dep_types.clone();
+        if !query.store_deps.is_empty() {
+            println!();
+            println!("Store-related dependencies of `{}`:", type_name);
+            for dep in &query.store_deps {
+                println!("  -> {}", dep);
             }
         }
-    }
-
-    fn add_type_deps(&mut self, type_name: &str, dep_types: Vec<String>) {
-        let mut deps = dep_types.clone();
-        if !deps.is_empty() {
-            deps.sort();
-            deps.dedup();
-            self.type_deps
-                .entry(type_name.to_string())
-                .or_default()
-                .extend(deps.clone());
+    } else {
+        println!("Type `{}` is NOT related to the store.", type_name);
+        println!(
+            "It is not reachable from any store root and can be changed without a store migration."
+        );
+        if let Some(span) = &query.span {
+            println!("Defined in: {}", span);
         }
     }
+}
 
-    fn visit_source_file(&mut self, file_path: &std::path::Path) {
-        let code = std::fs::read_to_string(file_path).unwrap();
-        if let Ok(file) = syn::parse_file(&code) {
-            let file_path = file_path.to_string_lossy();
-            if file_path.contains("/gen/") || file_path.contains("/migrations/") {
-                return;
-            }
-            self.in_rpc = file_path.contains("/rpc/");
-            self.current_file = file_path.to_string();
-            self.visit_file(&file);
-            self.in_rpc = false;
+/// Print types grouped by file path for readability.
+fn print_grouped(types: &[TypeLocation]) {
+    let mut last_file = "";
+    for location in types {
+        let f = location.file.as_deref().unwrap_or("(unknown)");
+        if f != last_file {
+            println!();
+            println!("  # {}", f);
+            last_file = f;
         }
+        println!("    {}", location.name);
     }
+}
 
-    fn collect_fingerprints(
-        &self,
-        type_name: &str,
-        visited: &mut HashMap<String, bool>,
-        fingerprints: &mut BTreeMap<String, String>,
-    ) {
-        if visited.contains_key(type_name) {
-            return;
-        }
-
-        // For types with custom `impl Serialize`: don't record their fingerprint.
-        // But still traverse their dependencies to find the actual serialized types.
-        // Dependencies from fields marked with `skip_store` have already been excluded
-        // when building type_deps.
-        let is_custom_serialize = self.custom_serializable_types.contains(type_name);
-        if !is_custom_serialize {
-            let finger = self.type_fingerprint.get(type_name);
-            if let Some(finger) = finger {
-                fingerprints.insert(type_name.to_string(), finger.clone());
-            }
-        }
-
-        visited.insert(type_name.to_string(), true);
-        if let Some(deps_vec) = self.type_deps.get(type_name) {
-            for dep in deps_vec {
-                self.collect_fingerprints(dep, visited, fingerprints);
-            }
-        }
-    }
+fn print_listing(listing: &TypeListing) {
+    println!(
+        "=== Types RELATED to store ({} types) ===",
+        listing.store_types.len()
+    );
+    print_grouped(&listing.store_types);
+
+    println!();
+    println!(
+        "=== Types NOT related to store ({} types) ===",
+        listing.non_store_types.len()
+    );
+    print_grouped(&listing.non_store_types);
+}
 
-    fn construct_finger_print(&self) -> BTreeMap<String, String> {
-        let mut dump_fingers = BTreeMap::new();
-        let mut visited = HashMap::new();
-        for type_name in &self.store_types {
-            self.collect_fingerprints(type_name, &mut visited, &mut dump_fingers);
-        }
-        dump_fingers
+/// Print the diagnostics of a check run, followed by hints on how to fix
+/// each class of failure.
+fn print_check(report: &CheckReport, cli: &Cli) {
+    if cli.format.is_machine_readable() {
+        println!("{}", render(cli.format, &report.diagnostics));
+        return;
     }
+    eprint!("{}", render_human(&report.diagnostics));
 
-    /// Collect types reachable from the store types that are actually part of
-    /// serialized data. A type is included if:
-    /// 1. It is a direct KeyValue variant type, OR
-    /// 2. It is a dependency of a type with `#[derive(Serialize)]`
-    ///
-    /// Types with custom `impl Serialize` are included but their field deps
-    /// are NOT followed (since we can't know which fields the custom impl
-    /// actually serializes).
-    ///
-    /// Types without any Serialize impl are NOT traversed — they appear in
-    /// fields that are never serialized (actor messages, error types, etc.).
-    fn collect_serializable_store_types(&self) -> HashSet<String> {
-        let builtin: HashSet<&str> = BUILTIN_TYPES.iter().copied().collect();
-        let mut result = HashSet::new();
-        let mut visited = HashSet::new();
-
-        fn collect_recursive(
-            visitor: &SynVisitor,
-            type_name: &str,
-            visited: &mut HashSet<String>,
-            result: &mut HashSet<String>,
-            builtin: &HashSet<&str>,
-        ) {
-            if visited.contains(type_name) || builtin.contains(type_name) {
-                return;
-            }
-            visited.insert(type_name.to_string());
-
-            // For types with custom `impl Serialize`: don't add to result,
-            // but still traverse their dependencies to find the actual serialized types.
-            // The dependencies have already been filtered to exclude skip_store fields.
-            let is_custom_serialize = visitor.custom_serializable_types.contains(type_name);
-            if !is_custom_serialize {
-                // Only include this type if it has a fingerprint (i.e., it was defined
-                // in the scanned source)
-                if visitor.type_fingerprint.contains_key(type_name) {
-                    result.insert(type_name.to_string());
-                }
-            }
-
-            // For types with #[derive(Serialize)] or custom impl Serialize:
-            // follow their field deps. For derive-serializable, we know all non-skipped
-            // fields are serialized. For custom Serialize, deps are already filtered.
-            if visitor.derive_serializable_types.contains(type_name) || is_custom_serialize {
-                if let Some(deps_vec) = visitor.type_deps.get(type_name) {
-                    for dep in deps_vec {
-                        collect_recursive(visitor, dep, visited, result, builtin);
-                    }
-                }
-                return;
-            }
-
-            // Types without any Serialize: don't traverse further.
-            // They appear in the dep graph but are not part of serialized data.
-        }
-
-        for type_name in &self.store_types {
-            collect_recursive(self, type_name, &mut visited, &mut result, &builtin);
-        }
-        result
+    if report.has_error(|k| matches!(k, DiagnosticKind::RpcMissingHexSerde { .. })) {
+        eprintln!("Please fix the errors in the RPC code");
     }
-
-    /// Check that all types included in the migration schema are defined in
-    /// the types-dir. Only checks types that are serializable and reachable
-    /// from KeyValue. Returns true if all checks pass.
-    pub fn check_store_types_in_types_dir(&self) -> bool {
-        let types_dir = match &self.types_dir {
-            Some(d) => d,
-            None => return true, // no types-dir specified, skip check
-        };
-
-        let builtin: HashSet<&str> = BUILTIN_TYPES.iter().copied().collect();
-        let store_types = self.collect_serializable_store_types();
-        let mut has_error = false;
-
-        for type_name in store_types.iter() {
-            // Skip builtin/external types
-            if builtin.contains(type_name.as_str()) {
-                continue;
-            }
-            // Check where this type is defined
-            if let Some(file_path) = self.type_file.get(type_name) {
-                if !file_path.contains(types_dir) {
-                    eprintln!(
-                        "WARNING: Store type `{}` is NOT defined in types-dir ({}), found in: {}",
-                        type_name, types_dir, file_path
-                    );
-                    // Print dependency chains for context
-                    let chains = self.try_find_type_chain(type_name, true);
-                    for chain in &chains {
-                        eprintln!("  Dependency chain: {}", chain);
-                    }
-                    has_error = true;
-                }
-            }
-        }
-
-        if has_error {
-            eprintln!();
-            eprintln!("Some store types are defined outside of the types crate.");
-            eprintln!("Please move them to the types crate to ensure migration safety.");
-        }
-
-        !has_error
+    if report.has_error(|k| matches!(k, DiagnosticKind::StoreTypeOutsideTypesDir { .. })) {
+        eprintln!();
+        eprintln!("Some store types are defined outside of the types crate.");
+        eprintln!("Please move them to the types crate to ensure migration safety.");
     }
-
-    /// Find dependency chains from KeyValue variants to a target type.
-    /// Returns chains like: `KeyValue::PaymentHistoryTimedResult -> Direction`
-    /// or `KeyValue::PaymentSession -> PaymentData -> SomeType`
-    ///
-    /// If `serialize_aware` is true, only follows deps through derive-Serialize types
-    /// (matching the collect_serializable_store_types logic). Otherwise follows all deps.
-    fn try_find_type_chain(&self, target_type: &str, serialize_aware: bool) -> Vec<String> {
-        let builtin: HashSet<&str> = BUILTIN_TYPES.iter().copied().collect();
-        let mut result = vec![];
-
-        for (variant_name, variant_deps) in &self.store_variants {
-            // Check if this variant can reach target_type
-            for dep in variant_deps {
-                let mut visited = HashSet::new();
-                let mut chain = vec![format!("KeyValue::{}", variant_name)];
-                if self.find_chain_to_target(
-                    target_type,
-                    dep,
-                    &mut chain,
-                    &mut visited,
-                    &builtin,
-                    serialize_aware,
-                ) {
-                    result.push(chain.join(" -> "));
-                }
-            }
-        }
-
-        // Deduplicate chains
-        result.sort();
-        result.dedup();
-        result
+    if report.has_error(|k| matches!(k, DiagnosticKind::StoreTypeNotDeserializable { .. })) {
+        eprintln!();
+        eprintln!("Some store types can be written but not read back.");
+        eprintln!("Please derive or implement `Deserialize` for them.");
     }
-
-    /// DFS to find a chain from `current` to `target_type`.
-    /// If `serialize_aware` is true, only follows deps through derive-Serialize types
-    /// and skips custom-Serialize types.
-    fn find_chain_to_target(
-        &self,
-        target_type: &str,
-        current: &str,
-        chain: &mut Vec<String>,
-        visited: &mut HashSet<String>,
-        builtin: &HashSet<&str>,
-        serialize_aware: bool,
-    ) -> bool {
-        if current == target_type {
-            chain.push(current.to_string());
-            return true;
-        }
-
-        if visited.contains(current) || builtin.contains(current) {
-            return false;
-        }
-        visited.insert(current.to_string());
-
-        if serialize_aware {
-            // Skip custom-Serialize types (we don't follow their deps)
-            if self.custom_serializable_types.contains(current) {
-                return false;
-            }
-
-            // Only follow deps for derive-Serialize types
-            if !self.derive_serializable_types.contains(current) {
-                return false;
-            }
-        }
-
-        if let Some(deps) = self.type_deps.get(current) {
-            chain.push(current.to_string());
-            for dep in deps {
-                let mut branch_chain = chain.clone();
-                let mut branch_visited = visited.clone();
-                if self.find_chain_to_target(
-                    target_type,
-                    dep,
-                    &mut branch_chain,
-                    &mut branch_visited,
-                    builtin,
-                    serialize_aware,
-                ) {
-                    *chain = branch_chain;
-                    visited.extend(branch_visited);
-                    return true;
+    if report.has_error(|k| {
+        matches!(
+            k,
+            DiagnosticKind::FingerprintChanged { .. }
+                | DiagnosticKind::StoreTypeRemoved { .. }
+                | DiagnosticKind::StoreTypeAdded { .. }
+        )
+    }) {
+        let mut command = String::from("migration-check");
+        if let Some(config) = &cli.config {
+            command.push_str(&format!(" --config {}", config.display()));
+        }
+        // Without -s, the sources and roots come from the configuration file
+        if !cli.source_code_dir.is_empty() {
+            command.push_str(&format!(" -s {}", cli.source_code_dir.join(" -s ")));
+            if cli.roots.is_empty() {
+                if let Some(root) = report.roots.first() {
+                    command.push_str(&format!(" -o {}", root.schema_path.display()));
                 }
-            }
-            chain.pop(); // backtrack
-        }
-
-        false
-    }
-
-    /// Query a single type: print whether it is reachable from KeyValue (store-related)
-    /// and if so, print all dependency chains from KeyValue to it.
-    pub fn query_type(&self, type_name: &str) {
-        // First check if the type exists at all in the scanned source
-        if !self.types.contains(&type_name.to_string()) {
-            eprintln!(
-                "Type `{}` was not found in the scanned source directories.",
-                type_name
-            );
-            eprintln!("Scanned directories: {:?}", self.dirs);
-            exit(1);
-        }
-
-        // Check if it's reachable from KeyValue (using serialize-aware collection)
-        let store_types = self.collect_serializable_store_types();
-        if store_types.contains(type_name) {
-            println!(
-                "Type `{}` is STORE-RELATED (reachable from KeyValue).",
-                type_name
-            );
-            println!();
-
-            // Print file location
-            if let Some(file) = self.type_file.get(type_name) {
-                println!("Defined in: {}", file);
-            }
-
-            // Print dependency chains (serialize-aware to match the actual tracking)
-            let chains = self.try_find_type_chain(type_name, true);
-            if chains.is_empty() {
-                println!("  (direct KeyValue variant type)");
             } else {
-                println!("Dependency chain(s) from KeyValue:");
-                for chain in &chains {
-                    println!("  {}", chain);
-                }
-            }
-
-            // Also show what types this type depends on (that are also store types)
-            if let Some(deps) = self.type_deps.get(type_name) {
-                let store_deps: Vec<&String> = deps
-                    .iter()
-                    .filter(|d| store_types.contains(d.as_str()))
-                    .collect();
-                if !store_deps.is_empty() {
-                    println!();
-                    println!("Store-related dependencies of `{}`:", type_name);
-                    for dep in &store_deps {
-                        println!("  -> {}", dep);
-                    }
+                for root in &report.roots {
+                    command.push_str(&format!(
+                        " --root {}={}",
+                        root.root,
+                        root.schema_path.display()
+                    ));
                 }
             }
-        } else {
-            println!("Type `{}` is NOT related to the store.", type_name);
-            println!(
-                "It is not reachable from KeyValue and can be changed without a store migration."
-            );
-            if let Some(file) = self.type_file.get(type_name) {
-                println!("Defined in: {}", file);
-            }
         }
+        eprintln!("migration check failed ...");
+        eprintln!(
+            "Please use `{} -u` to update the fingerprint, and remember to write a migration",
+            command
+        );
     }
 
-    /// Collect ALL type names reachable from KeyValue, following all deps
-    /// (not serialize-aware — includes everything in the transitive closure).
-    fn collect_all_store_reachable_types(&self) -> HashSet<String> {
-        let builtin: HashSet<&str> = BUILTIN_TYPES.iter().copied().collect();
-        let mut visited = HashSet::new();
-
-        fn walk(
-            visitor: &SynVisitor,
-            type_name: &str,
-            visited: &mut HashSet<String>,
-            builtin: &HashSet<&str>,
-        ) {
-            if visited.contains(type_name) || builtin.contains(type_name) {
-                return;
-            }
-            // Only include types that were actually defined in scanned source
-            if !visitor.type_fingerprint.contains_key(type_name)
-                && !visitor.type_deps.contains_key(type_name)
-            {
-                return;
-            }
-            visited.insert(type_name.to_string());
-            if let Some(deps) = visitor.type_deps.get(type_name) {
-                for dep in deps {
-                    walk(visitor, dep, visited, builtin);
-                }
-            }
+    if report.dumped {
+        for root in &report.roots {
+            eprintln!("dumped to: {}", root.schema_path.display());
         }
-
-        for type_name in &self.store_types {
-            walk(self, type_name, &mut visited, &builtin);
-        }
-        visited
+        eprintln!("migration check passed ...");
     }
+}
 
-    /// List all types NOT reachable from KeyValue.
-    /// Groups output by file path for readability.
-    pub fn list_non_store_types(&self) {
-        let all_store_types = self.collect_all_store_reachable_types();
-
-        // Collect all defined types (with fingerprints, i.e., structs/enums)
-        // that are NOT in the store reachable set
-        let mut non_store: Vec<(&String, Option<&String>)> = self
-            .types
-            .iter()
-            .filter(|t| !all_store_types.contains(t.as_str()))
-            .filter(|t| !BUILTIN_TYPES.contains(&t.as_str()))
-            .filter(|t| self.type_fingerprint.contains_key(t.as_str()))
-            .map(|t| (t, self.type_file.get(t)))
-            .collect();
-
-        // Sort by file path then by name
-        non_store.sort_by(|a, b| {
-            let fa = a.1.map(|s| s.as_str()).unwrap_or("");
-            let fb = b.1.map(|s| s.as_str()).unwrap_or("");
-            fa.cmp(fb).then(a.0.cmp(b.0))
-        });
-        non_store.dedup();
-
-        // Also collect store types for comparison
-        let mut store_list: Vec<(&String, Option<&String>)> = self
-            .types
-            .iter()
-            .filter(|t| all_store_types.contains(t.as_str()))
-            .filter(|t| !BUILTIN_TYPES.contains(&t.as_str()))
-            .filter(|t| self.type_fingerprint.contains_key(t.as_str()))
-            .map(|t| (t, self.type_file.get(t)))
-            .collect();
-        store_list.sort_by(|a, b| {
-            let fa = a.1.map(|s| s.as_str()).unwrap_or("");
-            let fb = b.1.map(|s| s.as_str()).unwrap_or("");
-            fa.cmp(fb).then(a.0.cmp(b.0))
-        });
-        store_list.dedup();
-
-        println!(
-            "=== Types RELATED to store ({} types) ===",
-            store_list.len()
-        );
-        let mut last_file = "";
-        for (type_name, file) in &store_list {
-            let f = file.map(|s| s.as_str()).unwrap_or("(unknown)");
-            if f != last_file {
-                println!();
-                println!("  # {}", f);
-                last_file = f;
-            }
-            println!("    {}", type_name);
-        }
-
-        println!();
-        println!(
-            "=== Types NOT related to store ({} types) ===",
-            non_store.len()
+fn main() {
+    let cli = Cli::parse();
+    let config_path = cli.config.clone().or_else(|| {
+        let cwd = std::env::current_dir().ok()?;
+        let path = Config::discover(&cwd)?;
+        // Keep the paths in diagnostics short when run next to the file
+        Some(
+            path.strip_prefix(&cwd)
+                .map(Path::to_path_buf)
+                .unwrap_or(path),
+        )
+    });
+    let mut config = match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(2);
+        }),
+        None => Config::default(),
+    };
+
+    if !cli.source_code_dir.is_empty() {
+        config.source_dirs = cli.source_code_dir.clone();
+    }
+    if config.source_dirs.is_empty() {
+        eprintln!(
+            "no source directories, use -s DIR or set `source-dirs` in {}",
+            CONFIG_FILE_NAME
         );
-        last_file = "";
-        for (type_name, file) in &non_store {
-            let f = file.map(|s| s.as_str()).unwrap_or("(unknown)");
-            if f != last_file {
-                println!();
-                println!("  # {}", f);
-                last_file = f;
-            }
-            println!("    {}", type_name);
-        }
+        exit(2);
     }
-
-    pub fn report_and_dump(&self, output: String, update: bool) {
-        if self.has_error {
-            eprintln!("Please fix the errors in src/rpc");
-            exit(1);
-        }
-
-        // Check store types are in types-dir (before generating schema)
-        if !self.check_store_types_in_types_dir() {
-            exit(1);
-        }
-
-        let old_finger: HashMap<String, String> = if !std::path::Path::new(&output).exists() {
-            Default::default()
-        } else {
-            let old_finger = std::fs::read_to_string(&output).unwrap();
-            serde_json::from_str(&old_finger).unwrap()
-        };
-        let new_finger = self.construct_finger_print();
-
-        let mut failed = false;
-        if !update {
-            for (type_name, old_finger) in old_finger.iter() {
-                if let Some(new_finger) = new_finger.get(type_name) {
-                    if old_finger != new_finger {
-                        eprintln!(
-                            "Type fingerprint changed: {} {} -> {}",
-                            type_name, old_finger, new_finger
-                        );
-                        eprintln!("Type dependency chain:");
-                        for chain in self.try_find_type_chain(type_name, false) {
-                            eprintln!("  {}", chain);
-                        }
-                        failed = true;
-                    }
-                }
+    if cli.types_dir.is_some() {
+        config.types_dir = cli.types_dir.clone();
+    }
+    if !cli.roots.is_empty() {
+        config.roots = cli.roots.clone();
+    }
+    if let Some(output) = &cli.output {
+        match config.roots.len() {
+            0 => config.output = Some(output.into()),
+            1 => config.roots[0].schema = Some(output.into()),
+            _ => {
+                eprintln!("-o/--output can only be used with a single store root, use --root NAME=SCHEMA instead");
+                exit(2);
             }
         }
-        if failed {
-            let dirs_str = self.dirs.join(" -s ");
-            eprintln!("migration check failed ...");
-            eprintln!(
-                "Please use `migration-check -s {} -o {} -u` to update the fingerprint, and remember to write a migration",
-                dirs_str, output
-            );
-            exit(1);
-        } else {
-            eprintln!("dumped to: {}", output.clone());
-            let dump_json = serde_json::to_string_pretty(&new_finger).unwrap();
-            let mut file = std::fs::File::create(output).unwrap();
-            std::io::Write::write_all(&mut file, dump_json.as_bytes()).unwrap();
-            eprintln!("migration check passed ...");
-        }
     }
-
-    pub fn walk_dir(&mut self) {
-        let dirs = self.dirs.clone();
-        let mut files = vec![];
-        for dir in &dirs {
-            for entry in WalkDir::new(dir).follow_links(true).into_iter() {
-                match entry {
-                    Ok(ref e)
-                        if !e.file_name().to_string_lossy().starts_with('.')
-                            && e.file_name().to_string_lossy().ends_with(".rs") =>
-                    {
-                        files.push(e.path().to_owned());
-                    }
-                    _ => (),
-                }
-            }
+    if cli.wire_format.is_some() {
+        config.wire_format = cli.wire_format;
+    }
+    config.severities.extend(cli.severities.iter().cloned());
+    if !cli.cfgs.is_empty() || !cli.features.is_empty() {
+        let cfg = config.cfg.get_or_insert_with(CfgSet::new);
+        for option in &cli.cfgs {
+            cfg.enable(option);
         }
-        // different order may produce different hash
-        files.sort();
-        for file_path in files {
-            self.visit_source_file(&file_path);
+        for feature in &cli.features {
+            cfg.enable_feature(feature);
         }
     }
-}
-
-impl Visit<'_> for SynVisitor {
-    fn visit_item_struct(&mut self, item_struct: &ItemStruct) {
-        self.inner_visit_item_struct(item_struct);
+    if let Some(parse_mode) = cli.parse_mode {
+        config.parse_mode = parse_mode;
+    }
+    if !cli.include.is_empty() {
+        config.paths.include = cli.include.clone();
+    }
+    for pattern in &cli.exclude {
+        config.paths.exclude(pattern);
+    }
+    config.paths.rules.extend(cli.path_rules.iter().cloned());
+    if cli.no_gitignore {
+        config.paths.gitignore = false;
     }
 
-    fn visit_item(&mut self, item: &syn::Item) {
-        match item {
-            syn::Item::Struct(item_struct) => self.inner_visit_item_struct(item_struct),
-            syn::Item::Enum(item_enum) => self.visit_item_enum(item_enum),
-            syn::Item::Type(item_type) => {
-                let type_name = item_type.ident.to_string();
-                self.types.push(type_name.clone());
-                self.record_type_file(&type_name);
-                let type_deps = self.calc_dep_types(*item_type.ty.clone());
-                self.add_type_deps(&type_name, type_deps.clone());
-            }
-            syn::Item::Impl(item_impl) => {
-                // Detect `impl Serialize for TypeName` to track custom Serialize impls
-                if let Some((_, ref trait_path, _)) = item_impl.trait_ {
-                    let trait_name = trait_path
-                        .segments
-                        .last()
-                        .map(|s| s.ident.to_string())
-                        .unwrap_or_default();
-                    if trait_name == "Serialize" {
-                        if let Type::Path(ref type_path) = *item_impl.self_ty {
-                            if let Some(seg) = type_path.path.segments.last() {
-                                self.custom_serializable_types.insert(seg.ident.to_string());
-                            }
-                        }
-                    }
+    // --feature-matrix: compare the schemas of several feature sets and exit
+    if !cli.feature_matrix.is_empty() {
+        let cfg_sets: Vec<CfgSet> = cli
+            .feature_matrix
+            .iter()
+            .map(|features| {
+                let mut cfg = config.cfg.clone().unwrap_or_default();
+                for feature in features.split(',').filter(|f| !f.trim().is_empty()) {
+                    cfg.enable_feature(feature);
                 }
-            }
-            _ => {}
+                cfg
+            })
+            .collect();
+        let report = Analyzer::check_matrix(&config, &cfg_sets).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        });
+        print_matrix(&report, &cli);
+        if !report.passed() {
+            exit(1);
         }
+        return;
     }
 
-    fn visit_item_enum(&mut self, item_enum: &'_ syn::ItemEnum) {
-        self.inner_visit_item_enum(item_enum);
-    }
-}
-
-#[derive(Parser)]
-#[command(author, version, about = "Schema migration checking tool")]
-struct Cli {
-    /// Source code directories to scan (can be specified multiple times)
-    #[clap(short, long, required = true, num_args = 1..)]
-    source_code_dir: Vec<String>,
-
-    /// Output file path
-    #[clap(short, long)]
-    output: Option<String>,
-
-    /// Types crate source directory. When specified, the tool will check that
-    /// all store-reachable types (from KeyValue enum) are defined within this
-    /// directory and error if any are found outside it.
-    #[clap(short, long)]
-    types_dir: Option<String>,
-
-    /// Force update fingerprint
-    #[arg(short = 'u', long, default_value_t = false)]
-    update: bool,
-
-    /// Query a type's store dependency chain. If the type is reachable from
-    /// KeyValue (i.e., it is persisted to the store), prints the dependency
-    /// chain(s). Otherwise prints that the type is not related to the store.
-    #[clap(short, long)]
-    query_type: Option<String>,
-
-    /// List all types defined in the scanned source that are NOT related to
-    /// the store (i.e., not reachable from KeyValue). Useful for identifying
-    /// types that can be safely moved without migration concerns.
-    #[clap(long, default_value_t = false)]
-    list_non_store_types: bool,
-}
-
-fn main() {
-    let cli = Cli::parse();
-    let mut visitor = SynVisitor::new(cli.source_code_dir.clone(), cli.types_dir.clone());
-    visitor.walk_dir();
+    let analyzer = Analyzer::new(config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
+    });
 
     // --query-type: query a single type and exit
     if let Some(ref type_name) = cli.query_type {
-        visitor.query_type(type_name);
+        match analyzer.query_type(type_name) {
+            Ok(query) if cli.format.is_machine_readable() => print_json(&query),
+            Ok(query) => print_query(&query),
+            Err(diagnostic) if cli.format.is_machine_readable() => {
+                println!("{}", render(cli.format, &[*diagnostic]));
+                exit(1);
+            }
+            Err(diagnostic) => {
+                eprint!("{}", render_human(&[*diagnostic]));
+                exit(1);
+            }
+        }
         return;
     }
 
     // --list-non-store-types: list all non-store types and exit
     if cli.list_non_store_types {
-        visitor.list_non_store_types();
+        let listing = analyzer.list_types();
+        if cli.format.is_machine_readable() {
+            print_json(&listing);
+        } else {
+            print_listing(&listing);
+        }
         return;
     }
 
-    let output = cli.output.clone().unwrap_or_else(|| {
-        let mut path = cli.source_code_dir.first().unwrap().clone();
-        path.push_str(".schema.json");
-        path
+    let report = analyzer.check(cli.update).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
     });
-    visitor.report_and_dump(output, cli.update);
+    print_check(&report, &cli);
+    if !report.passed() {
+        exit(1);
+    }
 }
@@ -0,0 +1,185 @@
+use crate::error::{Error, Result};
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the source files matching a path rule are scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathRole {
+    /// Scanned for store types. Files no rule matches are store code.
+    Store,
+    /// RPC code: number fields must be hex-encoded, and its types do not
+    /// shadow the store types of the same name.
+    Rpc,
+    /// Not scanned at all, e.g. generated code.
+    Ignore,
+}
+
+impl fmt::Display for PathRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathRole::Store => write!(f, "store"),
+            PathRole::Rpc => write!(f, "rpc"),
+            PathRole::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+impl FromStr for PathRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "store" => Ok(PathRole::Store),
+            "rpc" => Ok(PathRole::Rpc),
+            "ignore" => Ok(PathRole::Ignore),
+            _ => Err(format!(
+                "unknown path role `{}`, expected one of: store, rpc, ignore",
+                s
+            )),
+        }
+    }
+}
+
+/// Scan the files matching the glob `pattern` as `role`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRule {
+    pub role: PathRole,
+    pub pattern: String,
+}
+
+impl PathRule {
+    pub fn new(role: PathRole, pattern: impl Into<String>) -> Self {
+        PathRule {
+            role,
+            pattern: pattern.into(),
+        }
+    }
+}
+
+/// Parses `ROLE=GLOB`, e.g. `rpc=src/api/**`.
+impl FromStr for PathRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (role, pattern) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected ROLE=GLOB, got `{}`", s))?;
+        Ok(PathRule::new(role.trim().parse()?, pattern.trim()))
+    }
+}
+
+/// Which source files are scanned, and as what.
+///
+/// Patterns are globs in which `*` does not match `/` but `**` does. A
+/// file matches a pattern if either its path relative to its source
+/// directory or its path as found does, so `rpc/**` is the `rpc` module
+/// of a source directory and `**/rpc/**` is any `rpc` directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRules {
+    /// When not empty, only the files matching one of these are scanned.
+    pub include: Vec<String>,
+    /// The files matching one of these are skipped, whatever the rules say.
+    pub exclude: Vec<String>,
+    /// The role of each file that is not excluded is that of the last rule
+    /// it matches. The defaults scan `**/rpc/**` as RPC code and ignore
+    /// `**/gen/**` and `**/migrations/**`; later rules can override them.
+    pub rules: Vec<PathRule>,
+    /// Skip the files ignored by `.gitignore` and `.git/info/exclude`,
+    /// also outside a git repository.
+    pub gitignore: bool,
+}
+
+impl Default for PathRules {
+    fn default() -> Self {
+        PathRules {
+            include: vec![],
+            exclude: vec![],
+            rules: vec![
+                PathRule::new(PathRole::Rpc, "**/rpc/**"),
+                PathRule::new(PathRole::Ignore, "**/gen/**"),
+                PathRule::new(PathRole::Ignore, "**/migrations/**"),
+            ],
+            gitignore: true,
+        }
+    }
+}
+
+impl PathRules {
+    /// Skip the files matching `pattern`, even if a rule matches them.
+    pub fn exclude(&mut self, pattern: impl Into<String>) {
+        self.exclude.push(pattern.into());
+    }
+
+    pub(crate) fn compile(&self) -> Result<PathMatcher> {
+        let include = glob_set(&self.include)?;
+        let exclude = glob_set(&self.exclude)?;
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| Ok((glob(&rule.pattern)?.compile_matcher(), rule.role)))
+            .collect::<Result<_>>()?;
+        Ok(PathMatcher {
+            include,
+            exclude,
+            rules,
+        })
+    }
+}
+
+/// The set of `patterns`, `None` if there are none.
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(glob(pattern)?);
+    }
+    let set = builder.build().map_err(|source| Error::Glob {
+        pattern: patterns.join(", "),
+        source,
+    })?;
+    Ok(Some(set))
+}
+
+fn glob(pattern: &str) -> Result<Glob> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|source| Error::Glob {
+            pattern: pattern.to_string(),
+            source,
+        })
+}
+
+/// Compiled [`PathRules`].
+pub(crate) struct PathMatcher {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    rules: Vec<(GlobMatcher, PathRole)>,
+}
+
+impl PathMatcher {
+    /// The role of `path`, found under the source directory `dir`.
+    /// Files that are not included or are excluded are ignored.
+    pub(crate) fn role(&self, dir: &Path, path: &Path) -> PathRole {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        if let Some(include) = &self.include {
+            if !include.is_match(relative) && !include.is_match(path) {
+                return PathRole::Ignore;
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(relative) || exclude.is_match(path) {
+                return PathRole::Ignore;
+            }
+        }
+        self.rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.is_match(relative) || glob.is_match(path))
+            .map_or(PathRole::Store, |(_, role)| *role)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// Output format for diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// rustc-like text, see [`render_human`].
+    #[default]
+    Human,
+    /// A JSON array of diagnostics, see [`render_json`].
+    Json,
+    /// A SARIF 2.1.0 log, see [`render_sarif`].
+    Sarif,
+}
+
+impl Format {
+    /// Whether the output is meant for tools rather than people.
+    pub fn is_machine_readable(self) -> bool {
+        self != Format::Human
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!(
+                "unknown format `{}`, expected one of: human, json, sarif",
+                s
+            )),
+        }
+    }
+}
+
+pub fn render(format: Format, diagnostics: &[Diagnostic]) -> String {
+    match format {
+        Format::Human => render_human(diagnostics),
+        Format::Json => render_json(diagnostics),
+        Format::Sarif => render_sarif(diagnostics),
+    }
+}
+
+/// Render diagnostics in a rustc-like layout:
+///
+/// ```text
+/// error[MC0003]: type fingerprint changed: Channel 1a2b.. -> 3c4d..
+///   --> src/store/channel.rs
+///   = note: dependency chain: KeyValue::Channel -> Channel
+/// ```
+pub fn render_human(diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        writeln!(
+            out,
+            "{}[{}]: {}",
+            diagnostic.severity,
+            diagnostic.code,
+            diagnostic.message()
+        )
+        .unwrap();
+        if let Some(span) = &diagnostic.span {
+            writeln!(out, "  --> {}", span).unwrap();
+        }
+        for note in diagnostic.notes() {
+            writeln!(out, "  = note: {}", note).unwrap();
+        }
+    }
+    out
+}
+
+/// Render diagnostics as a JSON array. Each entry carries `code`,
+/// `severity`, `span`, `kind` and the kind's own fields, plus the rendered
+/// `message` and `notes`.
+pub fn render_json(diagnostics: &[Diagnostic]) -> String {
+    let entries: Vec<Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut entry = serde_json::to_value(diagnostic).unwrap();
+            entry["message"] = json!(diagnostic.message());
+            entry["notes"] = json!(diagnostic.notes());
+            entry
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries).unwrap()
+}
+
+/// Render diagnostics as a SARIF 2.1.0 log with a single run. Every
+/// diagnostic code that occurs becomes a rule of the tool driver.
+///
+/// Files under the current directory are given relative to `%SRCROOT%`,
+/// which is the current directory; other files get a `file://` URI.
+pub fn render_sarif(diagnostics: &[Diagnostic]) -> String {
+    let root = std::env::current_dir().unwrap_or_default();
+    sarif(diagnostics, &root)
+}
+
+/// [`render_sarif`] with `%SRCROOT%` at `root`, an absolute path.
+pub(crate) fn sarif(diagnostics: &[Diagnostic], root: &Path) -> String {
+    let rules: BTreeMap<&str, &str> = diagnostics
+        .iter()
+        .map(|d| (d.code, d.kind.name()))
+        .collect();
+    let rules: Vec<Value> = rules
+        .into_iter()
+        .map(|(code, name)| json!({ "id": code, "name": name }))
+        .collect();
+
+    let results: Vec<Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut text = diagnostic.message();
+            for note in diagnostic.notes() {
+                text.push('\n');
+                text.push_str(&note);
+            }
+            let mut result = json!({
+                "ruleId": diagnostic.code,
+                "level": match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                "message": { "text": text },
+            });
+            if let Some(span) = &diagnostic.span {
+                let mut location = json!({
+                    "physicalLocation": {
+                        "artifactLocation": artifact_location(&span.file, root),
+                    },
+                });
+                if let Some(line) = span.line {
+                    let mut region = json!({ "startLine": line });
+                    if let Some(column) = span.column {
+                        region["startColumn"] = json!(column);
+                    }
+                    location["physicalLocation"]["region"] = region;
+                }
+                result["locations"] = json!([location]);
+            }
+            result
+        })
+        .collect();
+
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "originalUriBaseIds": {
+                SRCROOT: { "uri": directory_uri(root) },
+            },
+            "results": results,
+        }],
+    });
+    serde_json::to_string_pretty(&log).unwrap()
+}
+
+/// The base id of the paths relative to the source root.
+const SRCROOT: &str = "%SRCROOT%";
+
+/// The SARIF `artifactLocation` of `file`: relative to [`SRCROOT`] if it is
+/// relative or under `root`, a `file://` URI otherwise.
+fn artifact_location(file: &str, root: &Path) -> Value {
+    let path = Path::new(file);
+    let relative = if path.is_absolute() {
+        path.strip_prefix(root).ok()
+    } else {
+        Some(path)
+    };
+    match relative {
+        Some(relative) => {
+            let segments: Vec<String> = relative
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .map(|component| encode(&component.as_os_str().to_string_lossy()))
+                .collect();
+            json!({ "uri": segments.join("/"), "uriBaseId": SRCROOT })
+        }
+        None => json!({ "uri": file_uri(path) }),
+    }
+}
+
+/// The `file://` URI of the absolute `path`.
+fn file_uri(path: &Path) -> String {
+    let segments: Vec<String> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(segment) => Some(encode(&segment.to_string_lossy())),
+            _ => None,
+        })
+        .collect();
+    format!("file:///{}", segments.join("/"))
+}
+
+/// The `file://` URI of the absolute directory `path`, with the trailing
+/// slash SARIF requires of a base URI.
+fn directory_uri(path: &Path) -> String {
+    let mut uri = file_uri(path);
+    if !uri.ends_with('/') {
+        uri.push('/');
+    }
+    uri
+}
+
+/// Percent-encode the characters of a path segment that may not appear in
+/// a URI as they are.
+fn encode(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
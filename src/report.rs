@@ -0,0 +1,97 @@
+use crate::diagnostic::{Diagnostic, DiagnosticKind, Span};
+use crate::schema::Schema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Type name -> sha256 of the type's normalized fingerprint text.
+pub type Fingerprints = BTreeMap<String, String>;
+
+/// Result of [`Analyzer::check`](crate::Analyzer::check).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckReport {
+    /// All findings: scan-time diagnostics (RPC fields, parse failures),
+    /// types-dir violations and fingerprint changes, in that order.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The schema computed from the current source, per root.
+    pub roots: Vec<RootReport>,
+    /// Whether the schema files were (re)written.
+    pub dumped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RootReport {
+    pub root: String,
+    pub schema_path: PathBuf,
+    pub schema: Schema,
+}
+
+impl CheckReport {
+    /// True if no diagnostic has error severity.
+    pub fn passed(&self) -> bool {
+        !self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    /// Whether any error diagnostic matches `pred`, e.g.
+    /// `report.has_error(|k| matches!(k, DiagnosticKind::FingerprintChanged { .. }))`.
+    pub fn has_error(&self, pred: impl Fn(&DiagnosticKind) -> bool) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.is_error() && pred(&d.kind))
+    }
+}
+
+/// Result of [`Analyzer::check_matrix`](crate::Analyzer::check_matrix).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MatrixReport {
+    /// One schema per cfg set, in the order given.
+    pub schemas: Vec<FeatureSchema>,
+    /// A `FeatureDependentLayout` diagnostic per type whose layout differs
+    /// between the cfg sets.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl MatrixReport {
+    /// True if no diagnostic has error severity.
+    pub fn passed(&self) -> bool {
+        !self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureSchema {
+    /// The cfg set, as printed by [`CfgSet`](crate::CfgSet).
+    pub cfg: String,
+    pub schema: Schema,
+}
+
+/// Result of [`Analyzer::query_type`](crate::Analyzer::query_type).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TypeQuery {
+    pub type_name: String,
+    /// Whether the type is reachable from a store root through serialized data.
+    pub store_related: bool,
+    /// Where the type is defined.
+    pub span: Option<Span>,
+    /// Dependency chains from the store roots. Empty for a direct root
+    /// variant type or for a type that is not store-related.
+    pub chains: Vec<String>,
+    /// Dependencies of this type that are also store types.
+    pub store_deps: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TypeLocation {
+    pub name: String,
+    pub file: Option<String>,
+}
+
+/// Result of [`Analyzer::list_types`](crate::Analyzer::list_types).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TypeListing {
+    /// Types reachable from a store root.
+    pub store_types: Vec<TypeLocation>,
+    /// Types not reachable from any store root; these can be changed without a
+    /// store migration.
+    pub non_store_types: Vec<TypeLocation>,
+}
@@ -0,0 +1,206 @@
+use crate::report::Fingerprints;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Version written to new schema files. Version 1 is the legacy format: a
+/// flat JSON object mapping type name to fingerprint hash. Versions 1 and 2
+/// hold legacy fingerprints, which ignore field names and the fields of
+/// tuple structs.
+///
+/// Version 3 is the fingerprint composition of this release: resolved
+/// field types, generics, serde directives, flattened children, codecs,
+/// hand-written impls, `repr` and discriminants, and folded aliases. No
+/// release wrote version 3 files with only part of these. Any change to
+/// what goes into a fingerprint must bump this version, as pinned by
+/// `test_fingerprints_pinned_to_schema_version`.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The contents of a `.schema.json` file.
+///
+/// ```json
+/// {
+///   "version": 3,
+///   "fingerprints": { "Channel": "1a2b..." },
+///   "types": {
+///     "Channel": {
+///       "kind": "struct",
+///       "fields": [{ "name": "id", "ty": "u64" }]
+///     }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    pub version: u32,
+    /// Type name -> sha256 of the type's fingerprint. A type is considered
+    /// changed when its hash differs; `types` explains what changed. Types
+    /// are named by their bare name, or by their full module path, like
+    /// `peer::Config`, if several types share the name.
+    pub fingerprints: Fingerprints,
+    /// Normalized structure of each type. Empty for legacy schema files.
+    #[serde(default)]
+    pub types: BTreeMap<String, TypeSchema>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Schema {
+            version: SCHEMA_VERSION,
+            fingerprints: Default::default(),
+            types: Default::default(),
+        }
+    }
+}
+
+/// The normalized structure of a store type, in declaration order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TypeSchema {
+    Struct {
+        fields: Vec<FieldSchema>,
+        /// Container `#[serde(...)]` directives, e.g. `transparent`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        serde: Vec<String>,
+        /// Hash of the hand-written `Serialize` and `Deserialize` impls and
+        /// the helper functions they call, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        custom_impl: Option<String>,
+    },
+    Enum {
+        variants: Vec<VariantSchema>,
+        /// Container `#[serde(...)]` directives, e.g. `tag = "type"`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        serde: Vec<String>,
+        /// The `#[repr(...)]` of the enum, e.g. `u8`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repr: Option<String>,
+        /// See `Struct::custom_impl`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        custom_impl: Option<String>,
+    },
+    /// A type alias, e.g. `type Amount = u64;`.
+    Alias {
+        /// The aliased type as written, e.g. `u64`.
+        target: String,
+    },
+}
+
+impl TypeSchema {
+    pub fn serde(&self) -> &[String] {
+        match self {
+            TypeSchema::Struct { serde, .. } | TypeSchema::Enum { serde, .. } => serde,
+            TypeSchema::Alias { .. } => &[],
+        }
+    }
+
+    pub fn custom_impl(&self) -> Option<&str> {
+        match self {
+            TypeSchema::Struct { custom_impl, .. } | TypeSchema::Enum { custom_impl, .. } => {
+                custom_impl.as_deref()
+            }
+            TypeSchema::Alias { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldSchema {
+    /// `None` for tuple fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The field type as written, e.g. `Option<Vec<Hop>>`.
+    pub ty: String,
+    /// `#[serde(...)]` and `#[serde_as(...)]` directives on the field, e.g.
+    /// `rename = "x"` or `as = "U64Hex"`, sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub serde: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariantSchema {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldSchema>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub serde: Vec<String>,
+    /// The explicit discriminant as written, e.g. `3` for `A = 3`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discriminant: Option<String>,
+}
+
+/// The name set by a `rename = "x"` directive in `serde`, if any.
+fn renamed(serde: &[String]) -> Option<&str> {
+    serde.iter().find_map(|directive| {
+        let (key, value) = directive.split_once('=')?;
+        (key.trim() == "rename").then(|| value.trim().trim_matches('"'))
+    })
+}
+
+impl FieldSchema {
+    /// The name the field is encoded under by named formats: its
+    /// `rename`, or its name. `None` for tuple fields.
+    pub fn wire_name(&self) -> Option<&str> {
+        renamed(&self.serde).or(self.name.as_deref())
+    }
+}
+
+impl VariantSchema {
+    /// The name the variant is encoded under by named formats.
+    pub fn wire_name(&self) -> &str {
+        renamed(&self.serde).unwrap_or(&self.name)
+    }
+}
+
+impl Schema {
+    /// Read a schema file in either the current or the legacy format.
+    /// A missing file is treated as an empty schema.
+    pub fn load(path: impl AsRef<Path>) -> Result<Schema> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Schema::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(&content).map_err(|source| Error::Schema {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Whether the fingerprints were computed the pre-version-3 way.
+    pub fn has_legacy_fingerprints(&self) -> bool {
+        self.version < 3
+    }
+
+    pub fn parse(content: &str) -> serde_json::Result<Schema> {
+        let value: serde_json::Value = serde_json::from_str(content)?;
+        if value.get("version").is_some() {
+            serde_json::from_value(value)
+        } else {
+            Ok(Schema {
+                version: 1,
+                fingerprints: serde_json::from_value(value)?,
+                types: Default::default(),
+            })
+        }
+    }
+
+    /// Write the schema in the current format.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut schema = serde_json::to_string_pretty(&Schema {
+            version: SCHEMA_VERSION,
+            ..self.clone()
+        })
+        .unwrap();
+        schema.push('\n');
+        std::fs::write(path, schema).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
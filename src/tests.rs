@@ -20,3 +20,1971 @@ fn test_bin_serde() {
     eprintln!("Decoded: {:?}", bincode_decoded);
     //assert_eq!(test_struct.a, bincode_decoded.a);
 }
+
+/// Source files written by `fixture`. The directory, and the schema files
+/// written next to it, are removed on drop.
+struct Fixture {
+    root: std::path::PathBuf,
+    dir: String,
+}
+
+impl std::ops::Deref for Fixture {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.dir
+    }
+}
+
+impl std::fmt::Display for Fixture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.dir)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Write `files` (relative path, source) into a fresh directory, unique to
+/// this call, under the system temp dir.
+fn fixture(name: &str, files: &[(&str, &str)]) -> Fixture {
+    static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let root = std::env::temp_dir().join(format!(
+        "migration-check-{}-{}-{}",
+        name,
+        std::process::id(),
+        count
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+    let dir = root.join("src");
+    for (path, code) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, code).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    Fixture {
+        dir: dir.to_string_lossy().into_owned(),
+        root,
+    }
+}
+
+fn analyze(dir: &str) -> crate::Analyzer {
+    crate::Analyzer::new(crate::Config::new(vec![dir.to_string()])).unwrap()
+}
+
+const STORE: &str = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        id: u64,
+        state: ChannelState,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum ChannelState {
+        Open,
+        Closed,
+    }
+
+    pub struct Actor {
+        channel: Channel,
+    }
+
+    pub enum KeyValue {
+        Channel(u64, Channel),
+    }
+"#;
+
+#[test]
+fn test_analyzer_check_and_update() {
+    let dir = fixture("check", &[("store.rs", STORE)]);
+    let schema = format!("{}.schema.json", dir);
+    let _ = std::fs::remove_file(&schema);
+
+    let report = analyze(&dir).check(false).unwrap();
+    assert!(report.passed());
+    assert!(report.dumped);
+    assert!(report.roots[0].schema.fingerprints.contains_key("Channel"));
+    assert!(report.roots[0]
+        .schema
+        .fingerprints
+        .contains_key("ChannelState"));
+    assert!(!report.roots[0].schema.fingerprints.contains_key("Actor"));
+
+    let changed = STORE.replace("id: u64", "id: u128");
+    std::fs::write(format!("{}/store.rs", dir), changed).unwrap();
+    let report = analyze(&dir).check(false).unwrap();
+    assert!(!report.passed());
+    assert!(!report.dumped);
+    assert_eq!(report.diagnostics.len(), 1);
+    assert_eq!(report.diagnostics[0].code, "MC0003");
+    assert!(matches!(
+        &report.diagnostics[0].kind,
+        crate::DiagnosticKind::FingerprintChanged { type_name, .. } if type_name == "Channel"
+    ));
+
+    let report = analyze(&dir).check(true).unwrap();
+    assert!(report.passed());
+    assert!(report.dumped);
+}
+
+#[test]
+fn test_analyzer_query_type() {
+    let dir = fixture("query", &[("store.rs", STORE)]);
+    let analyzer = analyze(&dir);
+
+    let query = analyzer.query_type("ChannelState").unwrap();
+    assert!(query.store_related);
+    assert_eq!(
+        query.chains,
+        vec!["KeyValue::Channel -> Channel -> ChannelState"]
+    );
+
+    assert!(!analyzer.query_type("Actor").unwrap().store_related);
+    let missing = analyzer.query_type("Missing").unwrap_err();
+    assert!(matches!(
+        missing.kind,
+        crate::DiagnosticKind::TypeNotFound { .. }
+    ));
+
+    let listing = analyzer.list_types();
+    let names =
+        |types: &[crate::TypeLocation]| types.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&listing.store_types), vec!["Channel", "ChannelState"]);
+    assert_eq!(names(&listing.non_store_types), vec!["Actor", "KeyValue"]);
+}
+
+#[test]
+fn test_fingerprints_pinned_to_schema_version() {
+    // A fingerprint of this version must not move: if it does, the way
+    // fingerprints are composed changed, and `SCHEMA_VERSION` must be bumped
+    assert_eq!(crate::SCHEMA_VERSION, 3);
+    let fee = r#"
+    pub type Amount = u64;
+
+    #[cfg_attr(any(), store_type)]
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Fee<T> {
+        #[serde(default)]
+        base_fee: Amount,
+        hops: Vec<(T, [u16; 2])>,
+        kind: Kind,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[repr(u8)]
+    pub enum Kind {
+        Fixed = 1,
+        Rate { ppm: u32 } = 2,
+    }
+"#;
+    let dir = fixture("pinned", &[("store.rs", STORE), ("fee.rs", fee)]);
+    let fingerprints = analyze(&dir).schema().fingerprints;
+    assert_eq!(
+        fingerprints["Channel"],
+        "8a06a8a350654f0d0cbbd48d64cc381cde0b3fa180b1c6f926e05656d33abb2f"
+    );
+    assert_eq!(
+        fingerprints["ChannelState"],
+        "633a06ebd3dd58e10c2e27a255e69a0434564b559e5dcb2ed125e4b7f0f997a8"
+    );
+    assert_eq!(
+        fingerprints["Fee"],
+        "2f8ca0c3ff0de8b43ba90999cc4bade85e361faf2d614449ce23c2c53c592a23"
+    );
+    assert_eq!(
+        fingerprints["Kind"],
+        "0fb28097f4c6551df8ddcf5b16cad5ac9626d3c25825ea9eaf7883b6b27050f8"
+    );
+}
+
+#[test]
+fn test_render_formats() {
+    use crate::{Diagnostic, DiagnosticKind, Span};
+    let diagnostics = vec![Diagnostic::new(
+        DiagnosticKind::RpcMissingHexSerde {
+            type_name: "Resp".to_string(),
+            field_name: "amount".to_string(),
+            expected: "U64Hex".to_string(),
+        },
+        Some(Span {
+            file: "./src/rpc/resp.rs".to_string(),
+            line: Some(3),
+            column: Some(5),
+        }),
+    )];
+
+    let json: serde_json::Value = serde_json::from_str(&crate::render_json(&diagnostics)).unwrap();
+    assert_eq!(json[0]["code"], "MC0001");
+    assert_eq!(json[0]["kind"], "RpcMissingHexSerde");
+    assert_eq!(json[0]["field_name"], "amount");
+    assert_eq!(json[0]["severity"], "error");
+
+    let sarif: serde_json::Value =
+        serde_json::from_str(&crate::render_sarif(&diagnostics)).unwrap();
+    let run = &sarif["runs"][0];
+    assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "MC0001");
+    let result = &run["results"][0];
+    assert_eq!(result["ruleId"], "MC0001");
+    assert_eq!(result["level"], "error");
+    let location = &result["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], "src/rpc/resp.rs");
+    assert_eq!(location["artifactLocation"]["uriBaseId"], "%SRCROOT%");
+    assert_eq!(location["region"]["startLine"], 3);
+    assert_eq!(location["region"]["startColumn"], 5);
+    let base = &run["originalUriBaseIds"]["%SRCROOT%"]["uri"];
+    assert!(base.as_str().unwrap().starts_with("file:///"));
+    assert!(base.as_str().unwrap().ends_with('/'));
+}
+
+#[test]
+fn test_sarif_absolute_source_dir() {
+    let dir = fixture(
+        "sarif",
+        &[
+            ("store.rs", STORE),
+            ("rpc/mod.rs", "pub struct Resp {\n    amount: u64,\n}\n"),
+        ],
+    );
+    assert!(std::path::Path::new(dir.as_str()).is_absolute());
+    let diagnostics = analyze(&dir).check(true).unwrap().diagnostics;
+    let location = |root: &std::path::Path| {
+        let sarif: serde_json::Value =
+            serde_json::from_str(&crate::render::sarif(&diagnostics, root)).unwrap();
+        sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"]["artifactLocation"]
+            .clone()
+    };
+
+    // Under the source root: relative to it
+    let root = std::path::Path::new(dir.as_str()).parent().unwrap();
+    let under = location(root);
+    assert_eq!(under["uri"], "src/rpc/mod.rs");
+    assert_eq!(under["uriBaseId"], "%SRCROOT%");
+
+    // Elsewhere: a file URI
+    let outside = location(std::path::Path::new("/nonexistent/checkout"));
+    assert_eq!(outside["uri"], format!("file://{}/rpc/mod.rs", dir));
+    assert!(outside.get("uriBaseId").is_none());
+}
+
+#[test]
+fn test_diagnostic_spans() {
+    let dir = fixture(
+        "spans",
+        &[
+            ("store.rs", STORE),
+            (
+                "rpc/channel.rs",
+                "pub struct ChannelInfo {\n    #[serde_as(as = \"U64Hex\")]\n    id: u64,\n    capacity: u128,\n}\n",
+            ),
+        ],
+    );
+    let analyzer = analyze(&dir);
+    let report = analyzer.check(true).unwrap();
+    assert_eq!(report.diagnostics.len(), 1);
+    let span = report.diagnostics[0].span.as_ref().unwrap();
+    assert!(span.file.ends_with("rpc/channel.rs"));
+    assert_eq!((span.line, span.column), (Some(4), Some(5)));
+    assert!(crate::render_human(&report.diagnostics)
+        .contains("field `capacity` of `ChannelInfo` expected serde_as: U128Hex"));
+
+    let query = analyzer.query_type("ChannelState").unwrap();
+    let span = query.span.unwrap();
+    assert_eq!((span.line, span.column), (Some(9), Some(14)));
+}
+
+#[test]
+fn test_rpc_hex_fields() {
+    let rpc = r#"
+    pub struct NodeInfo<'a> {
+        hash: [u8; 32],
+        chain: &'a [u64],
+        alias: &'a str,
+        timestamp: &'a u64,
+        #[serde_as(as = "Option<U64Hex>")]
+        fee: std::option::Option<u64>,
+        height: core::primitive::u64,
+        expiry: std::option::Option<u32>,
+    }
+"#;
+    let dir = fixture("rpc-hex", &[("store.rs", STORE), ("rpc/mod.rs", rpc)]);
+    let report = analyze(&dir).check(true).unwrap();
+    let mut expected = report
+        .diagnostics
+        .iter()
+        .filter_map(|d| match &d.kind {
+            crate::DiagnosticKind::RpcMissingHexSerde {
+                field_name,
+                expected,
+                ..
+            } => Some(format!("{}: {}", field_name, expected)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(expected, vec!["expiry: Option<U32Hex>", "height: U64Hex"]);
+}
+
+#[test]
+fn test_structured_schema() {
+    use crate::{FieldSchema, Schema, TypeSchema};
+    let dir = fixture("schema", &[("store.rs", STORE)]);
+    let schema_path = format!("{}.schema.json", dir);
+    let analyzer = analyze(&dir);
+    let schema = analyzer.schema();
+
+    assert_eq!(
+        schema.types["Channel"],
+        TypeSchema::Struct {
+            fields: vec![
+                FieldSchema {
+                    name: Some("id".to_string()),
+                    ty: "u64".to_string(),
+                    serde: vec![],
+                },
+                FieldSchema {
+                    name: Some("state".to_string()),
+                    ty: "ChannelState".to_string(),
+                    serde: vec![],
+                },
+            ],
+            serde: vec![],
+            custom_impl: None,
+        }
+    );
+
+    // A legacy hash-only schema file is still understood, and upgraded on write
+    let mut visitor = crate::visitor::SynVisitor::new(analyzer.config());
+    visitor.walk_dir().unwrap();
+    let legacy = visitor.legacy_fingerprints(&schema.fingerprints);
+    assert_ne!(legacy, schema.fingerprints);
+    let legacy = serde_json::to_string(&legacy).unwrap();
+    std::fs::write(&schema_path, legacy).unwrap();
+    let loaded = Schema::load(&schema_path).unwrap();
+    assert_eq!(loaded.version, 1);
+    assert!(loaded.types.is_empty());
+    let report = analyzer.check(false).unwrap();
+    assert!(report.passed());
+    assert_eq!(Schema::load(&schema_path).unwrap(), schema);
+}
+
+/// Structural changes of `name` between two versions of a store source,
+/// classified for `format` if given.
+fn classify_sources(
+    test: &str,
+    name: &str,
+    old: &str,
+    new: &str,
+    format: Option<crate::WireFormat>,
+) -> Vec<String> {
+    let old_dir = fixture(&format!("{}-old", test), &[("store.rs", old)]);
+    let new_dir = fixture(&format!("{}-new", test), &[("store.rs", new)]);
+    let old = &analyze(&old_dir).schema().types[name];
+    let new = &analyze(&new_dir).schema().types[name];
+    crate::classify_changes(format, old, new, crate::diff_types(old, new))
+        .iter()
+        .map(|change| change.to_string())
+        .collect()
+}
+
+fn diff_sources(test: &str, name: &str, old: &str, new: &str) -> Vec<String> {
+    classify_sources(test, name, old, new, None)
+}
+
+#[test]
+fn test_structural_diff() {
+    let changes = diff_sources(
+        "diff-struct",
+        "Channel",
+        STORE,
+        &STORE.replace("id: u64,", "fee: u32,\n        id: u128,"),
+    );
+    assert_eq!(
+        changes,
+        vec![
+            "field `fee` added at position 0: `u32`",
+            "field `id` type changed from `u64` to `u128`",
+        ]
+    );
+
+    let changes = diff_sources(
+        "diff-rename",
+        "Channel",
+        STORE,
+        &STORE.replace("id: u64", "channel_id: u64"),
+    );
+    assert_eq!(changes, vec!["field `id` renamed to `channel_id`"]);
+
+    let changes = diff_sources(
+        "diff-enum",
+        "ChannelState",
+        STORE,
+        &STORE.replace(
+            "Open,\n        Closed,",
+            "Closed,\n        Open,\n        Pending,",
+        ),
+    );
+    assert_eq!(
+        changes,
+        vec![
+            "variant `Pending` added at position 2",
+            "variant `Open` moved from position 0 to 1",
+            "variant `Closed` moved from position 1 to 0",
+        ]
+    );
+}
+
+#[test]
+fn test_serde_renames_and_aliases() {
+    use crate::WireFormat::{Bincode, Json};
+
+    // Fields and variants are paired by their wire name
+    let renamed = STORE.replace(
+        "id: u64",
+        "#[serde(rename = \"id\")]\n        channel_id: u64",
+    );
+    assert_eq!(
+        classify_sources("serde-rename", "Channel", STORE, &renamed, Some(Json)),
+        vec!["field `id` renamed to `channel_id` (compatible)"]
+    );
+    let aliased = STORE.replace(
+        "id: u64",
+        "#[serde(alias = \"id\")]\n        channel_id: u64",
+    );
+    assert_eq!(
+        classify_sources("serde-alias", "Channel", STORE, &aliased, Some(Json)),
+        vec!["field `id` renamed to `channel_id` (forward-only)"]
+    );
+    let renamed = STORE.replace("Open,", "#[serde(rename = \"Open\")]\n        Opened,");
+    assert_eq!(
+        classify_sources(
+            "serde-rename-variant",
+            "ChannelState",
+            STORE,
+            &renamed,
+            Some(Json)
+        ),
+        vec!["variant `Open` renamed to `Opened` (compatible)"]
+    );
+    let aliased = STORE.replace("Open,", "#[serde(alias = \"Open\")]\n        Opened,");
+    assert_eq!(
+        classify_sources(
+            "serde-alias-variant",
+            "ChannelState",
+            STORE,
+            &aliased,
+            Some(Json)
+        ),
+        vec!["variant `Open` renamed to `Opened` (forward-only)"]
+    );
+
+    // A new wire name for the same field
+    let rewired = STORE.replace(
+        "id: u64",
+        "#[serde(rename = \"channel_id\")]\n        id: u64",
+    );
+    let changes = classify_sources("serde-rewire", "Channel", STORE, &rewired, Some(Json));
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].starts_with("field `id` serde attributes changed"));
+    assert!(changes[0].ends_with("(needs migration)"));
+    assert_eq!(
+        classify_sources("serde-rewire", "Channel", STORE, &rewired, Some(Bincode)),
+        vec![changes[0].replace("needs migration", "compatible")]
+    );
+}
+
+#[test]
+fn test_compatibility_classification() {
+    use crate::WireFormat::{Bincode, Json, MessagePack};
+
+    // Renaming a field is invisible to bincode, as `test_bin_serde` shows,
+    // but breaks JSON
+    let renamed = STORE.replace("id: u64", "channel_id: u64");
+    assert_eq!(
+        classify_sources("compat-rename", "Channel", STORE, &renamed, Some(Bincode)),
+        vec!["field `id` renamed to `channel_id` (compatible)"]
+    );
+    assert_eq!(
+        classify_sources("compat-rename", "Channel", STORE, &renamed, Some(Json)),
+        vec!["field `id` renamed to `channel_id` (needs migration)"]
+    );
+
+    // Appending a variant keeps discriminants, inserting one shifts them
+    let appended = STORE.replace("Closed,", "Closed,\n        Pending,");
+    assert_eq!(
+        classify_sources(
+            "compat-append",
+            "ChannelState",
+            STORE,
+            &appended,
+            Some(Bincode)
+        ),
+        vec!["variant `Pending` added at position 2 (forward-only)"]
+    );
+    let inserted = STORE.replace("Open,", "Pending,\n        Open,");
+    assert_eq!(
+        classify_sources(
+            "compat-insert",
+            "ChannelState",
+            STORE,
+            &inserted,
+            Some(Bincode)
+        ),
+        vec!["variant `Pending` added at position 0 (needs migration)"]
+    );
+    assert_eq!(
+        classify_sources(
+            "compat-insert",
+            "ChannelState",
+            STORE,
+            &inserted,
+            Some(MessagePack)
+        ),
+        vec!["variant `Pending` added at position 0 (forward-only)"]
+    );
+
+    // MessagePack writes struct fields by position but variants by name
+    let container = |item: &str| {
+        STORE.replace(
+            item,
+            &format!("#[serde(rename_all = \"snake_case\")]\n    {}", item),
+        )
+    };
+    for (name, item, expected) in [
+        ("Channel", "pub struct Channel {", "compatible"),
+        ("ChannelState", "pub enum ChannelState {", "needs migration"),
+    ] {
+        assert_eq!(
+            classify_sources(
+                "compat-rename-all",
+                name,
+                STORE,
+                &container(item),
+                Some(MessagePack)
+            ),
+            vec![format!(
+                "serde attributes changed from [] to [\"rename_all = \\\"snake_case\\\"\"] ({})",
+                expected
+            )]
+        );
+    }
+
+    // A new defaulted field is only readable from old data by self-describing
+    // or length-prefixed formats
+    let added = STORE.replace(
+        "state: ChannelState,",
+        "state: ChannelState,\n        #[serde(default)]\n        fee: u64,",
+    );
+    for (format, expected) in [
+        (Bincode, "needs migration"),
+        (Json, "compatible"),
+        (MessagePack, "forward-only"),
+    ] {
+        assert_eq!(
+            classify_sources("compat-add", "Channel", STORE, &added, Some(format)),
+            vec![format!(
+                "field `fee` added at position 2: `u64` ({})",
+                expected
+            )]
+        );
+    }
+}
+
+#[test]
+fn test_wire_format_downgrades_compatible_changes() {
+    let dir = fixture("wire-format", &[("store.rs", STORE)]);
+    let config = crate::Config {
+        source_dirs: vec![dir.clone()],
+        wire_format: Some(crate::WireFormat::Bincode),
+        ..Default::default()
+    };
+    assert!(crate::Analyzer::new(config.clone())
+        .unwrap()
+        .check(true)
+        .unwrap()
+        .passed());
+
+    let appended = STORE.replace("Closed,", "Closed,\n        Pending,");
+    std::fs::write(format!("{}/store.rs", dir), appended).unwrap();
+    let report = crate::Analyzer::new(config.clone())
+        .unwrap()
+        .check(false)
+        .unwrap();
+    assert!(report.passed());
+    assert_eq!(report.diagnostics[0].severity, crate::Severity::Warning);
+
+    let widened = STORE.replace("id: u64", "id: u128");
+    std::fs::write(format!("{}/store.rs", dir), widened).unwrap();
+    let report = crate::Analyzer::new(config).unwrap().check(false).unwrap();
+    assert!(!report.passed());
+}
+
+#[test]
+fn test_store_type_added_and_removed() {
+    use crate::DiagnosticKind::{StoreTypeAdded, StoreTypeRemoved};
+    let dir = fixture("membership", &[("store.rs", STORE)]);
+    assert!(analyze(&dir).check(true).unwrap().passed());
+
+    // `ChannelState` is still defined but no longer reachable, and `Peer`
+    // is newly persisted
+    let changed = STORE.replace("state: ChannelState,", "").replace(
+        "Channel(u64, Channel),",
+        "Channel(u64, Channel),\n        Peer(Peer),",
+    ) + "#[derive(Serialize)] pub struct Peer { id: u64 }";
+    std::fs::write(format!("{}/store.rs", dir), changed).unwrap();
+    let report = analyze(&dir).check(false).unwrap();
+    let kinds: Vec<_> = report
+        .diagnostics
+        .iter()
+        .map(|d| (d.kind.clone(), d.severity))
+        .filter(|(k, _)| matches!(k, StoreTypeAdded { .. } | StoreTypeRemoved { .. }))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                StoreTypeRemoved {
+                    type_name: "ChannelState".to_string(),
+                    still_defined: true,
+                },
+                crate::Severity::Error
+            ),
+            (
+                StoreTypeAdded {
+                    type_name: "Peer".to_string(),
+                    chains: vec!["KeyValue::Peer -> Peer".to_string()],
+                },
+                crate::Severity::Warning
+            ),
+        ]
+    );
+
+    // Removals can be downgraded to warnings
+    let config = crate::Config {
+        source_dirs: vec![dir.clone()],
+        severities: [("store-type-removed".to_string(), crate::Severity::Warning)]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let report = crate::Analyzer::new(config).unwrap().check(false).unwrap();
+    assert!(report
+        .diagnostics
+        .iter()
+        .all(|d| d.code != "MC0006" || !d.is_error()));
+}
+
+#[test]
+fn test_configurable_roots() {
+    use crate::Root;
+    let dir = fixture(
+        "roots",
+        &[
+            (
+                "store.rs",
+                r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel { id: u64 }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Peer { id: u64 }
+
+    pub enum ChannelColumn { Channel(Channel) }
+"#,
+            ),
+            ("peer/store.rs", "pub enum PeerColumn { Peer(u64, Peer) }\n"),
+            (
+                "legacy/store.rs",
+                "pub enum PeerColumn { Stale(Channel) }\n",
+            ),
+        ],
+    );
+    let config = crate::Config {
+        source_dirs: vec![dir.clone()],
+        roots: vec![
+            Root::new("ChannelColumn"),
+            Root::new("peer::store::PeerColumn"),
+        ],
+        ..Default::default()
+    };
+    let analyzer = crate::Analyzer::new(config).unwrap();
+    let report = analyzer.check(true).unwrap();
+    assert!(report.passed());
+
+    let channel = &report.roots[0];
+    assert_eq!(
+        channel.schema_path,
+        std::path::PathBuf::from(format!("{}.ChannelColumn.schema.json", dir))
+    );
+    assert_eq!(
+        channel.schema.fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Channel"]
+    );
+
+    // The path spec does not match the `PeerColumn` enum in `legacy`
+    let peer = &report.roots[1];
+    assert_eq!(
+        peer.schema_path,
+        std::path::PathBuf::from(format!("{}.PeerColumn.schema.json", dir))
+    );
+    assert_eq!(
+        peer.schema.fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Peer"]
+    );
+    assert!(peer.schema_path.exists());
+
+    let query = analyzer.query_type("Peer").unwrap();
+    assert_eq!(query.chains, vec!["PeerColumn::Peer -> Peer".to_string()]);
+}
+
+#[test]
+fn test_attribute_roots_and_store_types() {
+    let dir = fixture(
+        "attribute-roots",
+        &[(
+            "store.rs",
+            r#"
+    #[derive(Serialize)]
+    pub struct Channel { id: u64 }
+
+    #[derive(Serialize)]
+    pub struct Data { len: u32 }
+
+    #[cfg_attr(any(), store_type)]
+    #[derive(Serialize)]
+    pub struct Blob { data: Data }
+
+    #[derive(Serialize)]
+    pub struct Unrelated { id: u64 }
+
+    #[cfg_attr(any(), migration_root)]
+    pub enum ChannelColumn { Channel(Channel) }
+"#,
+        )],
+    );
+    let analyzer = analyze(&dir);
+    let roots = analyzer.config().roots();
+    assert_eq!(roots, vec![crate::Root::new("ChannelColumn")]);
+
+    let schema = analyzer.root_schema(&roots[0]);
+    assert_eq!(
+        schema.fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Blob", "Channel", "Data"]
+    );
+    assert_eq!(
+        analyzer.query_type("Data").unwrap().chains,
+        vec!["Blob -> Data".to_string()]
+    );
+    assert!(!analyzer.query_type("Unrelated").unwrap().store_related);
+}
+
+#[test]
+fn test_output_goes_to_declared_root() {
+    let store = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Peer { id: u64 }
+
+    #[cfg_attr(any(), migration_root)]
+    pub enum PeerColumn { Peer(Peer) }
+"#;
+    let dir = fixture("output-declared", &[("store.rs", store)]);
+    let output = std::path::PathBuf::from(format!("{}/out.schema.json", dir));
+    let config = crate::Config {
+        output: Some(output.clone()),
+        ..crate::Config::new(vec![dir.to_string()])
+    };
+    let analyzer = crate::Analyzer::new(config).unwrap();
+    let roots = analyzer.config().roots();
+    assert_eq!(
+        roots,
+        vec![crate::Root {
+            schema: Some(output.clone()),
+            ..crate::Root::new("PeerColumn")
+        }]
+    );
+    let report = analyzer.check(true).unwrap();
+    assert!(report.passed());
+    assert_eq!(report.roots[0].schema_path, output);
+    let schema = crate::Schema::load(&output).unwrap();
+    assert!(schema.fingerprints.contains_key("Peer"));
+}
+
+#[test]
+fn test_declared_root_keeps_default_baseline() {
+    let dir = fixture("declared-root-baseline", &[("store.rs", STORE)]);
+    assert!(analyze(&dir).check(true).unwrap().passed());
+
+    // Declaring a second root must not hide a change to the first one
+    let peer = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Peer {
+        id: u64,
+    }
+
+    #[cfg_attr(any(), migration_root)]
+    pub enum PeerColumn {
+        Peer(Peer),
+    }
+"#;
+    std::fs::write(format!("{}/peer.rs", dir), peer).unwrap();
+    std::fs::write(
+        format!("{}/store.rs", dir),
+        STORE.replace("id: u64", "id: u128"),
+    )
+    .unwrap();
+    let report = analyze(&dir).check(false).unwrap();
+    assert!(!report.passed());
+    assert!(report.diagnostics.iter().any(|d| matches!(
+        &d.kind,
+        crate::DiagnosticKind::FingerprintChanged { type_name, .. } if type_name == "Channel"
+    )));
+
+    // Once accepted, the first root has its own file
+    let report = analyze(&dir).check(true).unwrap();
+    assert_eq!(
+        report.roots[0].schema_path,
+        std::path::PathBuf::from(format!("{}.KeyValue.schema.json", dir))
+    );
+    assert!(analyze(&dir).check(false).unwrap().passed());
+}
+
+#[test]
+fn test_path_qualified_type_identity() {
+    let store = r#"
+    use crate::channel::Config;
+
+    pub enum KeyValue {
+        Channel(Config),
+        Peer(crate::peer::Config),
+        Route(route::Hop),
+    }
+
+    mod route {
+        #[derive(Serialize)]
+        pub struct Hop { fee: u64 }
+    }
+"#;
+    let dir = fixture(
+        "identity",
+        &[
+            ("store.rs", store),
+            (
+                "channel.rs",
+                "#[derive(Serialize)]\npub struct Config { funding: u64, hop: super::store::route::Hop }\n",
+            ),
+            (
+                "peer.rs",
+                "#[derive(Serialize)]\npub struct Config { address: String }\n",
+            ),
+        ],
+    );
+    let analyzer = analyze(&dir);
+    let schema = analyzer.schema();
+    assert_eq!(
+        schema.fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Hop", "channel::Config", "peer::Config"]
+    );
+    assert_eq!(
+        analyzer.query_type("Hop").unwrap().chains,
+        vec![
+            "KeyValue::Channel -> channel::Config -> Hop".to_string(),
+            "KeyValue::Route -> Hop".to_string(),
+        ]
+    );
+    let err = analyzer.query_type("Config").unwrap_err();
+    assert_eq!(err.code, "MC0008");
+    assert!(analyzer.query_type("peer::Config").unwrap().store_related);
+
+    // Moving a type to another module keeps its name and fingerprint
+    let (unrouted, _) = store.split_once("mod route").unwrap();
+    let moved = fixture(
+        "identity-moved",
+        &[
+            (
+                "store.rs",
+                &unrouted.replace("route::Hop", "crate::hops::Hop"),
+            ),
+            (
+                "hops.rs",
+                "#[derive(Serialize)]\npub struct Hop { fee: u64 }\n",
+            ),
+        ],
+    );
+    assert_eq!(
+        analyze(&moved).schema().fingerprints["Hop"],
+        schema.fingerprints["Hop"]
+    );
+
+    // Naming a field type by another path or through an import is not a
+    // change of the type using it
+    for (name, channel) in [
+        (
+            "identity-path",
+            "#[derive(Serialize)]\npub struct Config { funding: u64, hop: crate::store::route::Hop }\n",
+        ),
+        (
+            "identity-import",
+            "use crate::store::route::Hop;\n#[derive(Serialize)]\npub struct Config { funding: u64, hop: Hop }\n",
+        ),
+    ] {
+        let dir = fixture(
+            name,
+            &[
+                ("store.rs", store),
+                ("channel.rs", channel),
+                (
+                    "peer.rs",
+                    "#[derive(Serialize)]\npub struct Config { address: String }\n",
+                ),
+            ],
+        );
+        assert_eq!(analyze(&dir).schema().fingerprints, schema.fingerprints);
+    }
+}
+
+#[test]
+fn test_schema_keys_survive_name_clashes() {
+    let dir = fixture("name-clash", &[("store.rs", STORE)]);
+    assert!(analyze(&dir).check(true).unwrap().passed());
+
+    // An unrelated `Channel` elsewhere qualifies the store type's name
+    let actor = "pub struct Channel {\n    peer: u64,\n}\n";
+    std::fs::write(format!("{}/actor.rs", dir), actor).unwrap();
+    let analyzer = analyze(&dir);
+    assert!(analyzer
+        .schema()
+        .fingerprints
+        .contains_key("store::Channel"));
+    let report = analyzer.check(false).unwrap();
+    assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+
+    // Changes are still found under the new name, and back
+    std::fs::write(
+        format!("{}/store.rs", dir),
+        STORE.replace("id: u64", "id: u128"),
+    )
+    .unwrap();
+    let report = analyze(&dir).check(false).unwrap();
+    assert_eq!(report.diagnostics.len(), 1);
+    assert!(matches!(
+        &report.diagnostics[0].kind,
+        crate::DiagnosticKind::FingerprintChanged { type_name, .. } if type_name == "store::Channel"
+    ));
+    assert!(analyze(&dir).check(true).unwrap().passed());
+    std::fs::remove_file(format!("{}/actor.rs", dir)).unwrap();
+    let report = analyze(&dir).check(false).unwrap();
+    assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+}
+
+#[test]
+fn test_import_aliases_globs_and_reexports() {
+    let store = r#"
+    use ckb_types::packed::Byte32 as Hash;
+    use crate::channel::Data as ChannelData;
+    use crate::peer::*;
+
+    #[derive(Serialize)]
+    pub struct Record {
+        hash: Hash,
+        data: ChannelData,
+        peer: Info,
+        amount: crate::types::Amount,
+    }
+
+    pub enum KeyValue {
+        Record(Record),
+    }
+"#;
+    let dir = fixture(
+        "imports",
+        &[
+            ("store.rs", store),
+            (
+                "channel.rs",
+                "#[derive(Serialize)]\npub struct Data { id: u64 }\n",
+            ),
+            (
+                "peer.rs",
+                "#[derive(Serialize)]\npub struct Info { id: u64 }\n",
+            ),
+            (
+                "types/mod.rs",
+                "mod amount;\npub use self::amount::Amount;\n",
+            ),
+            (
+                "types/amount.rs",
+                "#[derive(Serialize)]\npub struct Amount(u128);\n",
+            ),
+            // Same-named decoys that bare-name matching would pick up
+            (
+                "other.rs",
+                "#[derive(Serialize)] pub struct Hash { a: u8 }\n\
+                 #[derive(Serialize)] pub struct Info { a: u8 }\n\
+                 #[derive(Serialize)] pub struct Amount { a: u8 }\n",
+            ),
+        ],
+    );
+    let analyzer = analyze(&dir);
+    assert_eq!(
+        analyzer.schema().fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Data", "Record", "peer::Info", "types::amount::Amount"]
+    );
+    assert!(!analyzer.query_type("Hash").unwrap().store_related);
+    assert!(!analyzer.query_type("other::Info").unwrap().store_related);
+}
+
+#[test]
+fn test_tuple_and_unit_struct_fingerprints() {
+    let store = r#"
+    #[derive(Serialize)]
+    pub struct Amount(u128, std::time::Duration);
+
+    #[derive(Serialize)]
+    pub struct Marker;
+
+    pub enum KeyValue {
+        Amount(Amount),
+        Marker(Marker),
+    }
+"#;
+    let fingerprints = |name: &str, source: &str| {
+        let dir = fixture(name, &[("store.rs", source)]);
+        analyze(&dir).schema().fingerprints
+    };
+    let before = fingerprints("tuple", store);
+    assert_eq!(
+        analyze(&fixture("tuple", &[("store.rs", store)]))
+            .schema()
+            .types["Amount"],
+        crate::TypeSchema::Struct {
+            fields: vec![
+                crate::FieldSchema {
+                    name: None,
+                    ty: "u128".to_string(),
+                    serde: vec![],
+                },
+                crate::FieldSchema {
+                    name: None,
+                    ty: "std :: time :: Duration".to_string(),
+                    serde: vec![],
+                },
+            ],
+            serde: vec![],
+            custom_impl: None,
+        }
+    );
+
+    let narrowed = fingerprints("tuple-narrowed", &store.replace("u128", "u64"));
+    assert_ne!(before["Amount"], narrowed["Amount"]);
+    assert_eq!(before["Marker"], narrowed["Marker"]);
+
+    let unit_to_empty = fingerprints("tuple-unit", &store.replace("Marker;", "Marker {}"));
+    assert_ne!(before["Marker"], unit_to_empty["Marker"]);
+
+    let renamed = STORE.replace("id: u64", "channel_id: u64");
+    assert_ne!(
+        fingerprints("field-name", STORE)["Channel"],
+        fingerprints("field-renamed", &renamed)["Channel"]
+    );
+}
+
+#[test]
+fn test_generic_store_types() {
+    let store = r#"
+    #[derive(Serialize)]
+    pub struct Wrapper<T: Serialize> {
+        inner: T,
+        items: HashMap<u64, T>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Payment { amount: u64 }
+
+    #[derive(Serialize)]
+    pub struct T { unrelated: u8 }
+
+    pub enum KeyValue {
+        Payment(Wrapper<Payment>),
+    }
+"#;
+    let dir = fixture("generics", &[("store.rs", store)]);
+    let analyzer = analyze(&dir);
+    let schema = analyzer.schema();
+    // `T` in `Wrapper` is a placeholder, not the struct named `T`
+    assert_eq!(
+        schema.fingerprints.keys().collect::<Vec<_>>(),
+        vec!["Payment", "Wrapper"]
+    );
+    assert_eq!(
+        analyzer.query_type("Payment").unwrap().chains,
+        vec!["KeyValue::Payment -> Payment".to_string()]
+    );
+
+    // Bounds are part of the fingerprint
+    let bounded = store.replace("T: Serialize>", "T: Serialize + Clone>");
+    let dir = fixture("generics-bounded", &[("store.rs", &bounded)]);
+    assert_ne!(
+        analyze(&dir).schema().fingerprints["Wrapper"],
+        schema.fingerprints["Wrapper"]
+    );
+}
+
+#[test]
+fn test_dependencies_through_type_variants() {
+    let store = r#"
+    #[derive(Serialize)] pub struct InArray { a: u8 }
+    #[derive(Serialize)] pub struct InSlice { a: u8 }
+    #[derive(Serialize)] pub struct InReference { a: u8 }
+    #[derive(Serialize)] pub struct InPointer { a: u8 }
+    #[derive(Serialize)] pub struct InParens { a: u8 }
+    #[derive(Serialize)] pub struct InTuple { a: u8 }
+
+    #[derive(Serialize)]
+    pub struct Route<'a> {
+        array: [InArray; 4],
+        slice: Box<[InSlice]>,
+        reference: &'a InReference,
+        pointer: *const InPointer,
+        parens: (InParens),
+        tuple: (InTuple, u8),
+        callback: fn(u8) -> u8,
+        hash: [u8; 32],
+    }
+
+    pub enum KeyValue {
+        Route(Route<'static>),
+    }
+"#;
+    let dir = fixture("type-variants", &[("store.rs", store)]);
+    let fingerprints = analyze(&dir).schema().fingerprints;
+    assert_eq!(
+        fingerprints.keys().collect::<Vec<_>>(),
+        vec![
+            "InArray",
+            "InParens",
+            "InPointer",
+            "InReference",
+            "InSlice",
+            "InTuple",
+            "Route"
+        ]
+    );
+
+    // Array lengths are part of the fingerprint
+    let resized = store.replace("[u8; 32]", "[u8; 33]");
+    let dir = fixture("type-variants-resized", &[("store.rs", &resized)]);
+    assert_ne!(
+        analyze(&dir).schema().fingerprints["Route"],
+        fingerprints["Route"]
+    );
+}
+
+#[test]
+fn test_serde_attributes_in_fingerprints() {
+    let store = r#"
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Channel {
+        #[serde(default, rename = "id")]
+        channel_id: u64,
+        #[serde_as(as = "U64Hex")]
+        balance: u64,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+    }
+"#;
+    let schema = |name: &str, source: &str| {
+        let dir = fixture(name, &[("store.rs", source)]);
+        analyze(&dir).schema()
+    };
+    let before = schema("serde", store);
+    let crate::TypeSchema::Struct { fields, serde, .. } = &before.types["Channel"] else {
+        panic!("expected a struct");
+    };
+    assert_eq!(serde, &vec!["rename_all = \"camelCase\"".to_string()]);
+    assert_eq!(
+        fields[0].serde,
+        vec!["default".to_string(), "rename = \"id\"".to_string()]
+    );
+    assert_eq!(fields[1].serde, vec!["as = \"U64Hex\"".to_string()]);
+
+    for (name, changed) in [
+        ("serde-as", store.replace("U64Hex", "DisplayFromStr")),
+        (
+            "serde-rename",
+            store.replace("rename = \"id\"", "rename = \"cid\""),
+        ),
+        ("serde-container", store.replace("camelCase", "snake_case")),
+        (
+            "serde-untagged",
+            store.replace("rename_all = \"camelCase\"", "transparent"),
+        ),
+    ] {
+        let after = schema(name, &changed);
+        assert_ne!(
+            after.fingerprints["Channel"], before.fingerprints["Channel"],
+            "{}",
+            name
+        );
+        let changes = crate::diff_types(&before.types["Channel"], &after.types["Channel"]);
+        assert_eq!(changes.len(), 1, "{}", name);
+    }
+
+    // Directives that do not affect the encoding are ignored
+    let bounded = store.replace("#[serde(rename_all", "#[serde(bound = \"\", rename_all");
+    assert_eq!(
+        schema("serde-bound", &bounded).fingerprints["Channel"],
+        before.fingerprints["Channel"]
+    );
+}
+
+#[test]
+fn test_flatten_and_with_fields() {
+    let store = r#"
+    #[derive(Serialize)]
+    pub struct Channel {
+        #[serde(with = "crate::codec")]
+        secret: Secret,
+        #[serde(flatten)]
+        extra: Extra,
+    }
+
+    #[derive(Serialize)]
+    pub struct Secret {
+        key: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct Extra {
+        note: String,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+    }
+"#;
+    let codec = "pub fn serialize() { encode_hex() }";
+    let analyze_with = |name: &str, store: &str, codec: &str| {
+        let dir = fixture(name, &[("store.rs", store), ("codec.rs", codec)]);
+        analyze(&dir)
+    };
+    let analyzer = analyze_with("codecs", store, codec);
+    let before = analyzer.schema();
+    // The field is encoded by the codec, not by `Secret`'s own impl
+    assert!(before.fingerprints.contains_key("Extra"));
+    assert!(!before.fingerprints.contains_key("Secret"));
+    let query = analyzer.query_type("Secret").unwrap();
+    assert!(query.store_related);
+    assert_eq!(query.chains, vec!["KeyValue::Channel -> Channel -> Secret"]);
+    assert!(analyzer
+        .list_types()
+        .store_types
+        .iter()
+        .any(|location| location.name == "Secret"));
+
+    let fingerprints = |name: &str, store: &str, codec: &str| {
+        analyze_with(name, store, codec).schema().fingerprints
+    };
+    // Changing the codec's source changes the type using it
+    let after = fingerprints("codecs-codec", store, &codec.replace("hex", "base64"));
+    assert_ne!(after["Channel"], before.fingerprints["Channel"]);
+    assert_eq!(after["Extra"], before.fingerprints["Extra"]);
+
+    // A flattened child's fields are part of the parent's encoding
+    let after = fingerprints("codecs-flatten", &store.replace("note", "memo"), codec);
+    assert_ne!(after["Extra"], before.fingerprints["Extra"]);
+    assert_ne!(after["Channel"], before.fingerprints["Channel"]);
+
+    let after = fingerprints("codecs-secret", &store.replace("key", "id"), codec);
+    assert_eq!(after, before.fingerprints);
+}
+
+#[test]
+fn test_enum_discriminants_and_repr() {
+    use crate::WireFormat::Json;
+
+    let repr = STORE.replace(
+        "pub enum ChannelState {",
+        "#[repr(u8)]\n    pub enum ChannelState {",
+    );
+    let explicit = repr.replace("Closed,", "Closed = 5,");
+    assert_eq!(
+        diff_sources("repr", "ChannelState", STORE, &repr),
+        vec!["repr changed from implicit to `u8`"]
+    );
+    assert_eq!(
+        classify_sources("discriminant", "ChannelState", &repr, &explicit, Some(Json)),
+        vec!["variant `Closed` discriminant changed from implicit to `5` (needs migration)"]
+    );
+
+    // Reordering the variants of a repr enum changes their discriminants,
+    // even for formats that encode variants by name
+    let swap = |source: &str| source.replace("Open,\n        Closed,", "Closed,\n        Open,");
+    assert_eq!(
+        classify_sources("reorder", "ChannelState", STORE, &swap(STORE), Some(Json)),
+        vec![
+            "variant `Open` moved from position 0 to 1 (compatible)",
+            "variant `Closed` moved from position 1 to 0 (compatible)",
+        ]
+    );
+    assert_eq!(
+        classify_sources(
+            "reorder-repr",
+            "ChannelState",
+            &repr,
+            &swap(&repr),
+            Some(Json)
+        ),
+        vec![
+            "variant `Open` moved from position 0 to 1 (needs migration)",
+            "variant `Closed` moved from position 1 to 0 (needs migration)",
+        ]
+    );
+
+    // Inserting a variant shifts the implicit discriminants after it
+    let insert = |source: &str| source.replace("Closed,", "Pending,\n        Closed,");
+    assert_eq!(
+        classify_sources("insert", "ChannelState", STORE, &insert(STORE), Some(Json)),
+        vec!["variant `Pending` added at position 1 (forward-only)"]
+    );
+    assert_eq!(
+        classify_sources(
+            "insert-repr",
+            "ChannelState",
+            &repr,
+            &insert(&repr),
+            Some(Json)
+        ),
+        vec!["variant `Pending` added at position 1 (needs migration)"]
+    );
+    let append = |source: &str| source.replace("Closed,", "Closed,\n        Pending,");
+    assert_eq!(
+        classify_sources(
+            "append-repr",
+            "ChannelState",
+            &repr,
+            &append(&repr),
+            Some(Json)
+        ),
+        vec!["variant `Pending` added at position 2 (forward-only)"]
+    );
+}
+
+#[test]
+fn test_custom_impl_fingerprints() {
+    let store = r#"
+    pub struct Amount {
+        value: u64,
+    }
+
+    impl Serialize for Amount {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&encode_amount(self.value))
+        }
+    }
+
+    fn encode_amount(value: u64) -> String {
+        format!("{:x}", value)
+    }
+
+    pub enum KeyValue {
+        Amount(Amount),
+    }
+"#;
+    let schema = |name: &str, source: &str| {
+        let dir = fixture(name, &[("store.rs", source)]);
+        analyze(&dir).schema()
+    };
+    let before = schema("custom-impl", store);
+    assert!(before.types["Amount"].custom_impl().is_some());
+
+    // Changing the helper the impl calls changes the encoding
+    let after = schema("custom-impl-helper", &store.replace("{:x}", "{}"));
+    assert_ne!(after.fingerprints["Amount"], before.fingerprints["Amount"]);
+    let changes = crate::diff_types(&before.types["Amount"], &after.types["Amount"]);
+    assert_eq!(
+        changes.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+        vec!["hand-written serde impl changed"]
+    );
+    // Comments and formatting are not part of the impl's hash
+    let reformatted = store.replace(
+        "serializer.serialize_str(",
+        "// hex encoded\n            serializer.serialize_str(",
+    );
+    assert_eq!(
+        schema("custom-impl-comment", &reformatted).fingerprints,
+        before.fingerprints
+    );
+    // The fields a custom impl writes are unknown, only the impl counts
+    let after = schema(
+        "custom-impl-field",
+        &store.replace("value: u64,", "value: u64, cache: u8,"),
+    );
+    assert_eq!(after.fingerprints, before.fingerprints);
+
+    // A hand-written `Deserialize` adds to a derived type's fingerprint
+    let derived = "#[derive(Serialize)]\npub struct Amount {\n    value: u64,\n}\n\npub enum KeyValue {\n    Amount(Amount),\n}\n";
+    let with_deserialize = format!(
+        "{}\nimpl<'de> Deserialize<'de> for Amount {{\n    fn deserialize<D>(d: D) -> Result<Self, D::Error> {{ todo!() }}\n}}\n",
+        derived
+    );
+    let derived = schema("custom-impl-derived", derived);
+    let after = schema("custom-impl-deserialize", &with_deserialize);
+    assert_ne!(after.fingerprints["Amount"], derived.fingerprints["Amount"]);
+    assert_eq!(
+        crate::diff_types(&derived.types["Amount"], &after.types["Amount"])[0].to_string(),
+        "hand-written serde impl added"
+    );
+}
+
+#[test]
+fn test_deserialize_tracking() {
+    let store = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        state: State,
+    }
+
+    // Reads a legacy layout too
+    #[derive(Serialize)]
+    pub struct State {
+        flags: Flags,
+    }
+
+    impl<'de> Deserialize<'de> for State {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            todo!()
+        }
+    }
+
+    #[derive(Serialize)]
+    pub struct Flags(u8);
+
+    #[derive(Deserialize)]
+    pub struct Legacy {
+        old: OldFlags,
+    }
+
+    #[derive(Deserialize)]
+    pub struct OldFlags(u8);
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    pub struct Peer {
+        id: u64,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+        Legacy(Legacy),
+        Peer(Peer),
+    }
+"#;
+    let dir = fixture("deserialize", &[("store.rs", store)]);
+    let analyzer = analyze(&dir);
+    // Derives are recognized by path too
+    assert!(analyzer.schema().fingerprints.contains_key("Peer"));
+    let report = analyzer.check(true).unwrap();
+    let missing: Vec<_> = report
+        .diagnostics
+        .iter()
+        .filter(|d| d.code == "MC0009")
+        .collect();
+    // `State` has a custom impl; `Flags` is written but never read back
+    assert_eq!(missing.len(), 1);
+    assert_eq!(
+        missing[0].message(),
+        "store type `Flags` is serialized but cannot be deserialized"
+    );
+    assert_eq!(
+        missing[0].notes(),
+        vec!["dependency chain: KeyValue::Channel -> Channel -> State -> Flags"]
+    );
+    assert!(!report.passed());
+
+    // Types only read through a derived `Deserialize` are store data too
+    let query = analyzer.query_type("OldFlags").unwrap();
+    assert!(query.store_related);
+    assert_eq!(query.chains, vec!["KeyValue::Legacy -> Legacy -> OldFlags"]);
+}
+
+#[test]
+fn test_type_alias_fingerprints() {
+    let store = r#"
+    pub type Amount = u64;
+    pub type Fee = Amount;
+    pub type Hops = Vec<Hop>;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Hop {
+        fee: Fee,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        amount: Amount,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Unrelated {
+        id: u64,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+        Route(Hops),
+        Unrelated(Unrelated),
+    }
+"#;
+    let dir = fixture("alias", &[("store.rs", store)]);
+    let analyzer = analyze(&dir);
+    let before = analyzer.schema();
+    assert_eq!(
+        before.types["Amount"],
+        crate::TypeSchema::Alias {
+            target: "u64".to_string()
+        }
+    );
+    // Aliases are looked through like the types they stand for
+    assert_eq!(
+        analyzer.query_type("Hop").unwrap().chains,
+        vec!["KeyValue::Route -> Hops -> Hop"]
+    );
+
+    // A new alias target changes every type using it, even through
+    // other aliases
+    let widened = store.replace("Amount = u64", "Amount = u128");
+    let dir = fixture("alias-widened", &[("store.rs", &widened)]);
+    let after = analyze(&dir).schema();
+    for name in ["Amount", "Fee", "Hop", "Channel"] {
+        assert_ne!(
+            after.fingerprints[name], before.fingerprints[name],
+            "{}",
+            name
+        );
+    }
+    assert_eq!(
+        after.fingerprints["Unrelated"],
+        before.fingerprints["Unrelated"]
+    );
+    assert_eq!(
+        classify_sources(
+            "alias-classify",
+            "Amount",
+            store,
+            &widened,
+            Some(crate::WireFormat::Bincode)
+        ),
+        vec!["alias target changed from `u64` to `u128` (needs migration)"]
+    );
+
+    // Types using a changed alias carry its classification
+    let dir = fixture("alias-check", &[("store.rs", store)]);
+    let config = crate::Config {
+        source_dirs: vec![dir.to_string()],
+        wire_format: Some(crate::WireFormat::Json),
+        ..Default::default()
+    };
+    assert!(crate::Analyzer::new(config.clone())
+        .unwrap()
+        .check(true)
+        .unwrap()
+        .passed());
+    std::fs::write(format!("{}/store.rs", dir), &widened).unwrap();
+    let report = crate::Analyzer::new(config).unwrap().check(false).unwrap();
+    assert!(report.passed());
+    let changes = |name: &str| {
+        report
+            .diagnostics
+            .iter()
+            .find_map(|d| match &d.kind {
+                crate::DiagnosticKind::FingerprintChanged {
+                    type_name, changes, ..
+                } if type_name == name => {
+                    Some(changes.iter().map(|c| c.to_string()).collect::<Vec<_>>())
+                }
+                _ => None,
+            })
+            .unwrap()
+    };
+    assert_eq!(
+        changes("Hop"),
+        vec!["alias `Amount` changed from `u64` to `u128` (forward-only)"]
+    );
+    assert_eq!(changes("Fee"), changes("Hop"));
+    assert_eq!(changes("Channel"), changes("Hop"));
+}
+
+#[test]
+fn test_cfg_evaluation_and_feature_matrix() {
+    use crate::{CfgSet, TypeSchema};
+
+    let store = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        id: u64,
+        #[cfg(feature = "fees")]
+        fee: u64,
+        #[cfg_attr(all(feature = "fees", not(test)), serde(rename = "i"))]
+        index: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Peer {
+        id: u64,
+    }
+
+    #[cfg(feature = "mocks")]
+    #[derive(Serialize, Deserialize)]
+    pub struct Mock {
+        id: u64,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+        Peer(Peer),
+        #[cfg(feature = "mocks")]
+        Mock(Mock),
+    }
+
+    #[cfg(test)]
+    mod tests {
+        pub struct Harness {
+            channel: super::Channel,
+        }
+    }
+"#;
+    let dir = fixture("cfg", &[("store.rs", store)]);
+    let features = |features: &[&str]| {
+        let mut cfg = CfgSet::new();
+        for feature in features {
+            cfg.enable_feature(feature);
+        }
+        cfg
+    };
+    let fields = |cfg: Option<CfgSet>| {
+        let config = crate::Config {
+            source_dirs: vec![dir.clone()],
+            cfg,
+            ..Default::default()
+        };
+        let schema = crate::Analyzer::new(config).unwrap().schema();
+        let TypeSchema::Struct { fields, .. } = &schema.types["Channel"] else {
+            panic!("expected a struct");
+        };
+        fields
+            .iter()
+            .map(|field| format!("{}{:?}", field.name.clone().unwrap(), field.serde))
+            .collect::<Vec<_>>()
+    };
+
+    // Without a cfg set everything is scanned as one
+    assert_eq!(fields(None), vec!["id[]", "fee[]", "index[]"]);
+    assert_eq!(fields(Some(features(&[]))), vec!["id[]", "index[]"]);
+    assert_eq!(
+        fields(Some(features(&["fees"]))),
+        vec!["id[]", "fee[]", "index[\"rename = \\\"i\\\"\"]"]
+    );
+    let mut test = features(&["fees"]);
+    test.enable("test");
+    assert_eq!(fields(Some(test.clone())), vec!["id[]", "fee[]", "index[]"]);
+
+    // Test modules are only scanned when `test` is enabled
+    let has_harness = |cfg: Option<CfgSet>| {
+        let config = crate::Config {
+            source_dirs: vec![dir.clone()],
+            cfg,
+            ..Default::default()
+        };
+        crate::Analyzer::new(config)
+            .unwrap()
+            .query_type("Harness")
+            .is_ok()
+    };
+    assert!(!has_harness(None));
+    assert!(!has_harness(Some(features(&["fees"]))));
+    assert!(has_harness(Some(test)));
+
+    let config = crate::Config::new(vec![dir.clone()]);
+    let report = crate::Analyzer::check_matrix(
+        &config,
+        &[features(&[]), features(&["fees"]), features(&["mocks"])],
+    )
+    .unwrap();
+    assert_eq!(report.schemas.len(), 3);
+    assert_eq!(report.schemas[1].cfg, "feature=\"fees\"");
+    let flagged: Vec<String> = report.diagnostics.iter().map(|d| d.message()).collect();
+    assert_eq!(
+        flagged,
+        vec![
+            "persisted layout of `Channel` depends on the enabled features",
+            "persisted layout of `Mock` depends on the enabled features",
+        ]
+    );
+    assert!(report.diagnostics[1]
+        .notes()
+        .contains(&"with default: not persisted".to_string()));
+    assert!(!report.passed());
+}
+
+#[test]
+fn test_parse_failure_modes() {
+    use crate::{ParseMode, Severity};
+
+    let store = r#"#![allow(dead_code)]
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        id: u64,
+    }
+
+    pub struct Broken {
+        id: u64 @@
+    }
+
+    pub static DEFAULT: Peer = Peer { id: 0 };
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Peer {
+        id: u64,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+        Peer(Peer),
+    }
+"#;
+    let dir = fixture("parse-modes", &[("store.rs", store)]);
+    std::fs::write(
+        format!("{}/latin1.rs", dir),
+        b"// caf\xe9\npub struct Latin { id: u64 }\n",
+    )
+    .unwrap();
+    let analyze_in = |parse_mode: ParseMode| {
+        let config = crate::Config {
+            source_dirs: vec![dir.clone()],
+            parse_mode,
+            ..Default::default()
+        };
+        crate::Analyzer::new(config).unwrap()
+    };
+    let failures = |analyzer: &crate::Analyzer| {
+        let report = analyzer.check(true).unwrap();
+        let failures: Vec<_> = report
+            .diagnostics
+            .into_iter()
+            .filter(|d| d.code == "MC0005")
+            .collect();
+        failures
+    };
+
+    // By default the whole file is skipped, with a warning
+    let analyzer = analyze_in(ParseMode::Skip);
+    assert!(analyzer.schema().fingerprints.is_empty());
+    let skipped = failures(&analyzer);
+    assert_eq!(skipped.len(), 2);
+    assert!(skipped.iter().all(|d| d.severity == Severity::Warning));
+    assert!(skipped[0].message().contains("not valid UTF-8"));
+    assert_eq!(skipped[1].span.as_ref().unwrap().line, Some(8));
+
+    let analyzer = analyze_in(ParseMode::Strict);
+    assert!(failures(&analyzer)
+        .iter()
+        .all(|d| d.severity == Severity::Error));
+    assert!(!analyzer.check(true).unwrap().passed());
+
+    // Recovery analyzes every item that parses
+    let analyzer = analyze_in(ParseMode::Recover);
+    let fingerprints = analyzer.schema().fingerprints;
+    assert!(fingerprints.contains_key("Channel"));
+    assert!(fingerprints.contains_key("Peer"));
+    assert!(analyzer.query_type("Latin").is_ok());
+    assert!(analyzer.query_type("Broken").is_err());
+    let recovered = failures(&analyzer);
+    assert_eq!(recovered.len(), 2);
+    assert!(recovered[1]
+        .message()
+        .contains("only this item was skipped"));
+    assert_eq!(recovered[1].span.as_ref().unwrap().line, Some(8));
+}
+
+#[test]
+fn test_path_rules_and_gitignore() {
+    use crate::{DiagnosticKind, PathRole, PathRule, PathRules};
+
+    let unhexed = |name: &str| format!("pub struct {} {{\n    id: u64,\n}}\n", name);
+    let dir = fixture(
+        "path-rules",
+        &[
+            ("store.rs", STORE),
+            ("rpc/channel.rs", &unhexed("ChannelInfo")),
+            ("rpc_client/peer.rs", &unhexed("PeerInfo")),
+            ("api/node.rs", &unhexed("NodeInfo")),
+            ("gen/generated.rs", &unhexed("Generated")),
+            ("scratch.rs", &unhexed("Scratch")),
+            (".gitignore", "scratch.rs\n"),
+        ],
+    );
+    let analyze_with = |paths: PathRules| {
+        let config = crate::Config {
+            source_dirs: vec![dir.clone()],
+            paths,
+            ..Default::default()
+        };
+        crate::Analyzer::new(config)
+    };
+    let rpc_types = |analyzer: &crate::Analyzer| {
+        let mut types: Vec<String> = analyzer
+            .check(true)
+            .unwrap()
+            .diagnostics
+            .into_iter()
+            .filter_map(|d| match d.kind {
+                DiagnosticKind::RpcMissingHexSerde { type_name, .. } => Some(type_name),
+                _ => None,
+            })
+            .collect();
+        types.sort();
+        types
+    };
+
+    // `rpc_client` is not RPC code, generated and git-ignored files are skipped
+    let analyzer = analyze_with(PathRules::default()).unwrap();
+    assert_eq!(rpc_types(&analyzer), vec!["ChannelInfo"]);
+    assert!(analyzer.query_type("PeerInfo").is_ok());
+    assert!(analyzer.query_type("Generated").is_err());
+    assert!(analyzer.query_type("Scratch").is_err());
+
+    // Later rules override the defaults
+    let mut paths = PathRules {
+        gitignore: false,
+        ..Default::default()
+    };
+    paths.rules.push("rpc=api/**".parse().unwrap());
+    paths
+        .rules
+        .push(PathRule::new(PathRole::Store, "**/gen/**"));
+    paths.exclude("rpc_client/*.rs");
+    let analyzer = analyze_with(paths).unwrap();
+    assert_eq!(rpc_types(&analyzer), vec!["ChannelInfo", "NodeInfo"]);
+    assert!(analyzer.query_type("Generated").is_ok());
+    assert!(analyzer.query_type("Scratch").is_ok());
+    assert!(analyzer.query_type("PeerInfo").is_err());
+
+    let analyzer = analyze_with(PathRules {
+        include: vec!["store.rs".to_string()],
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(rpc_types(&analyzer).is_empty());
+    assert!(analyzer.schema().fingerprints.contains_key("Channel"));
+
+    let mut paths = PathRules::default();
+    paths.exclude("gen/[");
+    assert!(matches!(
+        analyze_with(paths),
+        Err(crate::Error::Glob { .. })
+    ));
+    assert!("web=api/**".parse::<PathRule>().is_err());
+}
+
+#[test]
+fn test_config_file() {
+    use crate::{Config, DiagnosticKind, Error, Severity, WireFormat};
+    use std::path::Path;
+
+    let store = r#"
+    #[derive(Serialize, Deserialize)]
+    pub struct Channel {
+        id: u64,
+        key: crate::vendor::Pubkey,
+    }
+
+    pub enum KeyValue {
+        Channel(Channel),
+    }
+"#;
+    let config_file = r#"
+source-dirs = ["src"]
+types-dir = "src/types"
+roots = [{ name = "KeyValue", schema = "store.schema.json" }]
+wire-format = "bincode"
+external-types = ["u8", "u64", "vendor::Pubkey"]
+
+[paths]
+exclude = ["**/api/debug/**"]
+
+[rpc]
+paths = ["api/**"]
+hex-types = ["u64"]
+
+[severity]
+rpc-missing-hex-serde = "warning"
+"#;
+    let dir = fixture(
+        "config-file",
+        &[
+            ("migration-check.toml", config_file),
+            ("src/types/store.rs", store),
+            (
+                "src/vendor.rs",
+                "#[derive(Serialize, Deserialize)]\npub struct Pubkey([u8; 33]);\n",
+            ),
+            (
+                "src/api/node.rs",
+                "pub struct NodeInfo {\n    id: u64,\n    port: u16,\n}\n",
+            ),
+            (
+                "src/api/debug/dump.rs",
+                "pub struct Dump {\n    height: u64,\n}\n",
+            ),
+        ],
+    );
+    let root = Path::new(dir.as_str());
+    let path = Config::discover(&root.join("src/api")).unwrap();
+    assert_eq!(path, root.join(crate::CONFIG_FILE_NAME));
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.source_dirs, vec![format!("{}/src", dir)]);
+    assert_eq!(config.types_dir, Some(format!("{}/src/types", dir)));
+    assert_eq!(config.roots[0].schema, Some(root.join("store.schema.json")));
+    assert_eq!(config.wire_format, Some(WireFormat::Bincode));
+    assert_eq!(config.rpc.hex_types, vec!["u64"]);
+    assert_eq!(config.external_types, vec!["u8", "u64", "vendor::Pubkey"]);
+    assert!(Config::default()
+        .external_types
+        .contains(&"PeerId".to_string()));
+
+    // Only `id` must be hex: the vendored key may live outside the types
+    // dir, and the excluded `api/debug` is not RPC code despite `api/**`
+    let analyzer = crate::Analyzer::new(config).unwrap();
+    let report = analyzer.check(true).unwrap();
+    assert!(report.passed());
+    assert_eq!(report.diagnostics.len(), 1);
+    assert_eq!(report.diagnostics[0].severity, Severity::Warning);
+    assert!(matches!(
+        &report.diagnostics[0].kind,
+        DiagnosticKind::RpcMissingHexSerde { field_name, .. } if field_name == "id"
+    ));
+    assert!(root.join("store.schema.json").is_file());
+
+    let config = Config {
+        external_types: Config::default().external_types,
+        ..Config::load(&path).unwrap()
+    };
+    let report = crate::Analyzer::new(config).unwrap().check(true).unwrap();
+    assert!(report
+        .diagnostics
+        .iter()
+        .any(|d| matches!(d.kind, DiagnosticKind::StoreTypeOutsideTypesDir { .. })));
+
+    for invalid in ["wire-format = \"xml\"", "sources = [\"src\"]"] {
+        std::fs::write(&path, invalid).unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::Config { .. })));
+    }
+}
@@ -0,0 +1,2186 @@
+use crate::cfg::CfgSet;
+use crate::diagnostic::{Diagnostic, DiagnosticKind, Severity, Span};
+use crate::paths::{PathRole, PathRules};
+use crate::report::{Fingerprints, TypeListing, TypeLocation, TypeQuery};
+use crate::schema::{FieldSchema, Schema, TypeSchema, VariantSchema};
+use crate::{Config, Error, ParseMode, Result};
+use ignore::WalkBuilder;
+use proc_macro2::{TokenStream, TokenTree};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use syn::punctuated::Punctuated;
+use syn::visit::Visit;
+use syn::Type;
+use syn::{Fields, ItemStruct, Token};
+
+/// How many `use` hops to follow when resolving a path, to cut cycles.
+const MAX_IMPORT_DEPTH: usize = 16;
+
+/// The `use` declarations of a module.
+#[derive(Default)]
+struct Imports {
+    /// Local name -> imported path, e.g. `Hash` -> `ckb_types::packed::Byte32`
+    /// for `use ckb_types::packed::Byte32 as Hash`.
+    names: HashMap<String, Vec<String>>,
+    /// Paths imported with `*`, e.g. `crate::types` for `use crate::types::*`.
+    globs: Vec<Vec<String>>,
+}
+
+/// The items of a file that fails to parse as a whole, and where each
+/// chunk that is not an item starts, with its error (see `parse_items`).
+type RecoveredItems = (Vec<syn::Item>, Vec<(proc_macro2::Span, syn::Error)>);
+
+/// A hand-written `impl Serialize` or `impl Deserialize`.
+struct CustomImpl {
+    /// The module of the impl.
+    module: Vec<String>,
+    /// The implementing type as written.
+    ty: String,
+    /// The impl's tokens, printed, which drops comments and formatting.
+    code: String,
+    /// Identifiers used in the impl, to find the helper functions it calls.
+    idents: BTreeSet<String>,
+}
+
+/// Every identifier in `tokens`, including nested groups.
+fn token_idents(tokens: TokenStream, idents: &mut BTreeSet<String>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => {
+                idents.insert(ident.to_string());
+            }
+            TokenTree::Group(group) => token_idents(group.stream(), idents),
+            _ => {}
+        }
+    }
+}
+
+/// The key of a type: `store::channel::Channel` for `Channel` defined in
+/// module `["store", "channel"]`.
+fn path_key(module: &[String], name: &str) -> String {
+    module
+        .iter()
+        .map(String::as_str)
+        .chain([name])
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// The last segment of a path, e.g. `Channel` for `store::channel::Channel`.
+fn bare_name(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+/// The module a type key is defined in.
+fn key_module(key: &str) -> Vec<String> {
+    let mut module: Vec<String> = key.split("::").map(str::to_string).collect();
+    module.pop();
+    module
+}
+
+/// Scans source files and builds the type dependency graph.
+///
+/// Types are identified by their full module path, like
+/// `store::channel::Channel`, so that same-named types in different modules
+/// stay apart. Dependencies are recorded as written while scanning and
+/// resolved to these keys once all files are scanned (see `resolve`).
+/// Output uses display names: the bare type name unless another type shares
+/// it, so that moving a file does not rename its types. A schema check
+/// pairs the two names of a type whose name became shared or unshared.
+pub(crate) struct SynVisitor {
+    types: Vec<String>,
+    type_fingerprint: HashMap<String, String>,
+    /// Fingerprints as computed before schema version 3, which ignored
+    /// tuple struct fields and field names, to compare against older
+    /// schema files.
+    legacy_fingerprint: HashMap<String, String>,
+    /// The text hashed into each type's fingerprint, with the type paths
+    /// between `⟦⟧` as written, see `fingerprint_type`. `resolve` replaces
+    /// them with the keys they refer to before hashing.
+    raw_fingerprint: HashMap<String, String>,
+    /// Normalized structure of each type, recorded alongside its fingerprint.
+    type_schema: HashMap<String, TypeSchema>,
+    /// Types each type depends on. Paths as written until `resolve`, keys
+    /// (or bare names of external types) afterwards.
+    type_deps: HashMap<String, Vec<String>>,
+    /// Root specs to match enums against: a bare name like `KeyValue` or a
+    /// path like `store::KeyValue`.
+    roots: Vec<String>,
+    /// The key of each matched root enum, keyed by root spec.
+    store_types: BTreeMap<String, String>,
+    /// Enums marked `#[migration_root]` that no configured spec matches.
+    /// They are roots of their own, keyed by enum name in `store_types`.
+    declared_roots: Vec<String>,
+    /// Types marked `#[store_type]`: persisted on their own, e.g. as blobs
+    /// under raw keys, rather than through a root enum.
+    standalone_types: Vec<String>,
+    /// Root variant info: (root_key, variant_name, dep_types) for each variant.
+    /// Used to produce meaningful dep chains like `KeyValue::Variant -> Type -> Target`.
+    store_variants: Vec<(String, String, Vec<String>)>,
+    /// All source directories to scan
+    dirs: Vec<String>,
+    /// Which files under `dirs` are scanned, and as what.
+    paths: PathRules,
+    /// Types not expected to be defined in `dirs`, see
+    /// `Config::external_types`.
+    external_types: Vec<String>,
+    /// Field types RPC code must encode as hex, see `check_rpc_field`.
+    hex_types: Vec<String>,
+    /// The cfg options to evaluate `#[cfg]` and `cfg_attr` against, if any.
+    cfg: Option<CfgSet>,
+    parse_mode: ParseMode,
+    /// Optional: the types-dir path prefix. Types defined in files under this
+    /// directory are considered "in the types crate".
+    types_dir: Option<String>,
+    /// Records where each type was first defined (see `record_type_span`).
+    type_span: HashMap<String, Span>,
+    /// Types that derive `Serialize` via `#[derive(Serialize)]`.
+    /// For these types, we know ALL non-skipped fields are serialized, so we
+    /// follow their field deps in the types-dir check.
+    derive_serializable_types: HashSet<String>,
+    /// Types that have a custom `impl Serialize for T`.
+    /// For these, we can't determine which fields are serialized from syntax
+    /// alone, so we include them in the check but DON'T follow their field deps.
+    custom_serializable_types: HashSet<String>,
+    /// Type aliases. They are encoded as their target, so traversals look
+    /// through them, and their fingerprint is part of every type using them.
+    aliases: HashSet<String>,
+    /// Types that derive `Deserialize`. Old data is read through these, so,
+    /// like for derived `Serialize`, all non-skipped fields are stored data.
+    derive_deserializable_types: HashSet<String>,
+    /// Types with a custom `impl Deserialize`, e.g. one that also accepts a
+    /// legacy layout. Which fields they read is not known from syntax.
+    custom_deserializable_types: HashSet<String>,
+    /// `impl Serialize for T` targets as written, with the module of the impl.
+    serialize_impls: Vec<(Vec<String>, String)>,
+    /// `impl Deserialize for T` targets, like `serialize_impls`.
+    deserialize_impls: Vec<(Vec<String>, String)>,
+    /// Hand-written `Serialize` and `Deserialize` impls, see `resolve`.
+    custom_impls: Vec<CustomImpl>,
+    /// Hash of the hand-written impls of each type, see `resolve`.
+    impl_hashes: HashMap<String, String>,
+    /// Paths of the code named by `with`, `serialize_with` and
+    /// `deserialize_with` fields, as written, by type. That code's source is
+    /// part of the type's fingerprint.
+    codecs: HashMap<String, Vec<String>>,
+    /// Types of the fields encoded by such code. Like the fields of custom
+    /// `impl Serialize` types, their encoding is unknown, so they are kept
+    /// out of `type_deps` and only count as store-reachable.
+    codec_deps: HashMap<String, Vec<String>>,
+    /// Types of `#[serde(flatten)]` fields, whose fields are encoded inline
+    /// in the parent, by type. Their fingerprints are part of the parent's.
+    flattened: HashMap<String, Vec<String>>,
+    /// Hash of the source of each module and free function, by path, e.g.
+    /// `codec::hex` for `src/codec/hex.rs`.
+    code_hashes: HashMap<String, String>,
+    /// `use` declarations of each module, by module path.
+    imports: HashMap<Vec<String>, Imports>,
+    /// Keys of the types sharing each bare name.
+    by_name: HashMap<String, Vec<String>>,
+    /// Display name of each type, see the type docs.
+    display_names: HashMap<String, String>,
+    /// Type parameters of the type being visited, see `enter_generics`.
+    generic_params: HashSet<String>,
+    in_rpc: bool,
+    /// Files scanned as RPC code, see `PathRole::Rpc`.
+    rpc_files: HashSet<String>,
+    /// Findings made while scanning: RPC field checks and parse failures.
+    diagnostics: Vec<Diagnostic>,
+    current_file: String,
+    /// Module path of `current_file`, derived from its location in the
+    /// source directory, e.g. `["store", "channel"]` for `src/store/channel.rs`.
+    current_module: Vec<String>,
+}
+
+impl SynVisitor {
+    pub(crate) fn new(config: &Config) -> Self {
+        SynVisitor {
+            types: Vec::new(),
+            type_fingerprint: HashMap::new(),
+            legacy_fingerprint: HashMap::new(),
+            raw_fingerprint: HashMap::new(),
+            type_schema: HashMap::new(),
+            type_deps: HashMap::new(),
+            roots: config.roots().into_iter().map(|root| root.name).collect(),
+            store_types: BTreeMap::new(),
+            declared_roots: Vec::new(),
+            standalone_types: Vec::new(),
+            store_variants: Vec::new(),
+            dirs: config.source_dirs.clone(),
+            paths: config.paths.clone(),
+            external_types: config.external_types.clone(),
+            hex_types: config.rpc.hex_types.clone(),
+            cfg: config.cfg.clone(),
+            parse_mode: config.parse_mode,
+            types_dir: config.types_dir.clone(),
+            type_span: HashMap::new(),
+            derive_serializable_types: HashSet::new(),
+            custom_serializable_types: HashSet::new(),
+            aliases: HashSet::new(),
+            derive_deserializable_types: HashSet::new(),
+            custom_deserializable_types: HashSet::new(),
+            serialize_impls: Vec::new(),
+            deserialize_impls: Vec::new(),
+            custom_impls: Vec::new(),
+            impl_hashes: HashMap::new(),
+            codecs: HashMap::new(),
+            codec_deps: HashMap::new(),
+            flattened: HashMap::new(),
+            code_hashes: HashMap::new(),
+            imports: HashMap::new(),
+            by_name: HashMap::new(),
+            display_names: HashMap::new(),
+            generic_params: HashSet::new(),
+            in_rpc: false,
+            rpc_files: HashSet::new(),
+            diagnostics: Vec::new(),
+            current_file: String::new(),
+            current_module: Vec::new(),
+        }
+    }
+
+    /// The root spec matching an enum named `enum_name` in the current
+    /// module, if any. A path spec like `store::KeyValue` only matches when
+    /// the module path ends with `store`.
+    fn matching_root(&self, enum_name: &str) -> Option<String> {
+        self.roots
+            .iter()
+            .find(|spec| {
+                let mut segments: Vec<&str> = spec.split("::").map(str::trim).collect();
+                if segments.pop() != Some(enum_name) {
+                    return false;
+                }
+                if segments.first() == Some(&"crate") {
+                    segments.remove(0);
+                }
+                self.current_module
+                    .ends_with(&segments.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+            })
+            .cloned()
+    }
+
+    /// The paths of the types `ty` refers to, as written, e.g.
+    /// `["Option", "crate::hop::Hop"]` for `Option<crate::hop::Hop>`.
+    /// Type parameters of the type being visited are placeholders, so only
+    /// the concrete arguments a generic type is used with are dependencies.
+    fn calc_dep_types(&self, ty: Type) -> Vec<String> {
+        let mut dep_types = vec![];
+        match ty {
+            Type::Path(type_path) => {
+                let segments = &type_path.path.segments;
+                let is_param = type_path.qself.is_none()
+                    && segments.first().is_some_and(|segment| {
+                        self.generic_params.contains(&segment.ident.to_string())
+                    });
+                if is_param {
+                    // `T` or `T::Assoc`
+                    return dep_types;
+                }
+                dep_types.push(
+                    segments
+                        .iter()
+                        .map(|segment| segment.ident.to_string())
+                        .collect::<Vec<_>>()
+                        .join("::"),
+                );
+                for segment in segments {
+                    if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                        for arg in &args.args {
+                            if let syn::GenericArgument::Type(ty) = arg {
+                                dep_types.extend(self.calc_dep_types(ty.clone()));
+                            }
+                        }
+                    }
+                }
+            }
+            Type::Tuple(type_tuple) => {
+                for elem in &type_tuple.elems {
+                    dep_types.extend(self.calc_dep_types(elem.clone()));
+                }
+            }
+            // `[Hop; 4]`, `[Hop]`, `&'a Hop`, `*const Hop`, `(Hop)` and
+            // macro-generated groups all depend on their element type
+            Type::Array(type_array) => dep_types.extend(self.calc_dep_types(*type_array.elem)),
+            Type::Slice(type_slice) => dep_types.extend(self.calc_dep_types(*type_slice.elem)),
+            Type::Reference(type_ref) => dep_types.extend(self.calc_dep_types(*type_ref.elem)),
+            Type::Ptr(type_ptr) => dep_types.extend(self.calc_dep_types(*type_ptr.elem)),
+            Type::Paren(type_paren) => dep_types.extend(self.calc_dep_types(*type_paren.elem)),
+            Type::Group(type_group) => dep_types.extend(self.calc_dep_types(*type_group.elem)),
+            // Trait objects, `impl Trait`, function pointers, `!`, `_`,
+            // macros and verbatim tokens name no data type that could be
+            // persisted. `Type` is non-exhaustive, so this also covers
+            // future variants.
+            _ => {}
+        }
+        dep_types
+    }
+
+    /// The names of the path types in an RPC field type, by their last
+    /// segment, e.g. `["Option", "u64"]` for `std::option::Option<u64>`.
+    /// Arrays, slices and references are left alone: `[u8; 32]` is not a
+    /// number to hex-encode.
+    fn rpc_field_types(ty: &Type) -> Vec<String> {
+        let mut types = vec![];
+        match ty {
+            Type::Path(type_path) => {
+                let Some(last) = type_path.path.segments.last() else {
+                    return types;
+                };
+                types.push(last.ident.to_string());
+                if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+                    for arg in &args.args {
+                        if let syn::GenericArgument::Type(ty) = arg {
+                            types.extend(Self::rpc_field_types(ty));
+                        }
+                    }
+                }
+            }
+            Type::Tuple(type_tuple) => {
+                for elem in &type_tuple.elems {
+                    types.extend(Self::rpc_field_types(elem));
+                }
+            }
+            _ => {}
+        }
+        types
+    }
+
+    /// `ty` for a fingerprint, with every path to a type other than a type
+    /// parameter written between `⟦⟧`, e.g. `Vec<⟦route::Hop⟧>`, so that
+    /// `resolve` can replace it with what it refers to: naming the same
+    /// type by another path or through an import is not a change.
+    fn fingerprint_type(&self, ty: &Type) -> String {
+        let arguments = |arguments: &syn::PathArguments| match arguments {
+            syn::PathArguments::AngleBracketed(args) => {
+                let args: Vec<String> = args
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        syn::GenericArgument::Type(ty) => self.fingerprint_type(ty),
+                        arg => quote::quote! { #arg }.to_string(),
+                    })
+                    .collect();
+                format!("<{}>", args.join(", "))
+            }
+            arguments => quote::quote! { #arguments }.to_string(),
+        };
+        match ty {
+            Type::Path(type_path) if type_path.qself.is_none() => {
+                let segments = &type_path.path.segments;
+                let is_param = segments.first().is_some_and(|segment| {
+                    self.generic_params.contains(&segment.ident.to_string())
+                });
+                if is_param {
+                    return quote::quote! { #ty }.to_string();
+                }
+                let path: Vec<String> = segments.iter().map(|s| s.ident.to_string()).collect();
+                let args: String = segments.iter().map(|s| arguments(&s.arguments)).collect();
+                format!("⟦{}⟧{}", path.join("::"), args)
+            }
+            Type::Tuple(type_tuple) => {
+                let elems: Vec<String> = type_tuple
+                    .elems
+                    .iter()
+                    .map(|elem| self.fingerprint_type(elem))
+                    .collect();
+                format!("({})", elems.join(", "))
+            }
+            Type::Array(type_array) => {
+                let len = &type_array.len;
+                let len = quote::quote! { #len };
+                format!("[{}; {}]", self.fingerprint_type(&type_array.elem), len)
+            }
+            Type::Slice(type_slice) => format!("[{}]", self.fingerprint_type(&type_slice.elem)),
+            Type::Reference(type_ref) => {
+                let lifetime = &type_ref.lifetime;
+                let mutability = &type_ref.mutability;
+                let prefix = quote::quote! { & #lifetime #mutability }.to_string();
+                format!("{} {}", prefix, self.fingerprint_type(&type_ref.elem))
+            }
+            Type::Ptr(type_ptr) => {
+                let mutability = if type_ptr.mutability.is_some() {
+                    "mut"
+                } else {
+                    "const"
+                };
+                format!("*{} {}", mutability, self.fingerprint_type(&type_ptr.elem))
+            }
+            Type::Paren(type_paren) => self.fingerprint_type(&type_paren.elem),
+            Type::Group(type_group) => self.fingerprint_type(&type_group.elem),
+            ty => quote::quote! { #ty }.to_string(),
+        }
+    }
+
+    /// Make the type parameters of `generics` placeholders for the type
+    /// about to be visited, and return the fingerprint line recording them
+    /// with their bounds, which is empty for non-generic types.
+    fn enter_generics(&mut self, generics: &syn::Generics) -> String {
+        self.generic_params = generics
+            .type_params()
+            .map(|param| param.ident.to_string())
+            .collect();
+        if generics.params.is_empty() && generics.where_clause.is_none() {
+            return String::new();
+        }
+        let where_clause = &generics.where_clause;
+        format!("generics:{}\n", quote::quote! { #generics #where_clause })
+    }
+
+    /// Check if the item attributes include `#[derive(<name>, ...)]`, e.g.
+    /// `#[derive(Serialize, serde::Deserialize)]` for `Serialize` or
+    /// `Deserialize`. Paths are compared by their last segment.
+    fn has_derive(attrs: &[syn::Attribute], name: &str) -> bool {
+        attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("derive"))
+            .filter_map(|attr| {
+                attr.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                    .ok()
+            })
+            .flatten()
+            .any(|path| path.segments.last().is_some_and(|last| last.ident == name))
+    }
+
+    /// Returns true if `attrs` carry the migration-check annotation `marker`,
+    /// either as `#[marker]` or as `#[cfg_attr(any(), marker)]`, which keeps
+    /// the annotation out of the compiled code.
+    fn has_marker(attrs: &[syn::Attribute], marker: &str) -> bool {
+        attrs.iter().any(|attr| {
+            if attr.path().is_ident(marker) {
+                return true;
+            }
+            match &attr.meta {
+                syn::Meta::List(meta_list) if attr.path().is_ident("cfg_attr") => meta_list
+                    .tokens
+                    .clone()
+                    .into_iter()
+                    .any(|token| matches!(token, TokenTree::Ident(ident) if ident == marker)),
+                _ => false,
+            }
+        })
+    }
+
+    /// Returns true if the field should be excluded from the fingerprint and
+    /// dependency graph. This includes:
+    /// - `#[serde(skip)]`, `#[serde(skip_serializing)]`, `#[serde(skip_deserializing)]`
+    /// - `#[skip_store]` - explicit annotation for migration-check
+    /// - `#[cfg_attr(any(), skip_store)]` - conditional skip_store annotation
+    fn should_skip_field(&self, field: &syn::Field) -> bool {
+        if Self::has_marker(&field.attrs, "skip_store") {
+            return true;
+        }
+        field.attrs.iter().any(|attr| {
+            let attr_name = attr.path().segments.last().unwrap().ident.to_string();
+
+            // Check for serde skip attributes
+            if attr_name == "serde" {
+                let tokens = self.get_attr_tokens(attr);
+                // Split by comma to check each individual directive.
+                // e.g. #[serde(skip, default)] → tokens = "skip , default"
+                return tokens.split(',').any(|part| {
+                    let trimmed = part.trim();
+                    trimmed == "skip"
+                        || trimmed == "skip_serializing"
+                        || trimmed == "skip_deserializing"
+                });
+            }
+
+            false
+        })
+    }
+
+    // check if the field is a number of `hex_types` and has the serde_as
+    // attribute with the expected value
+    // e.g. #[serde_as(as = "Option<u8>")]
+    // or #[serde_as(as = "u8")]
+    fn check_rpc_field(&mut self, struct_name: &str, field: &syn::Field) {
+        let dep_types = Self::rpc_field_types(&field.ty);
+        if dep_types.len() > 2 {
+            return;
+        }
+        let Some(last) = dep_types.last() else {
+            return;
+        };
+        let Some(field_name) = &field.ident else {
+            return;
+        };
+        if !self.hex_types.contains(last) {
+            return;
+        }
+        let is_option = dep_types.len() == 2 && dep_types[0] == "Option";
+
+        let serde_attrs = field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("serde_as") || attr.path().is_ident("serde"))
+            .collect::<Vec<_>>();
+        let expected_hex = format!("{}Hex", last.to_uppercase());
+        let expected_serde_as_value = if is_option {
+            format!("Option<{}>", expected_hex)
+        } else {
+            expected_hex
+        };
+
+        if !serde_attrs.iter().any(|attr| {
+            let attr_str = self.get_attr_tokens(attr);
+            if let Some(attr_value) = attr_str.split('=').nth(1) {
+                attr_value.contains(&expected_serde_as_value)
+            } else {
+                false
+            }
+        }) {
+            self.diagnostics.push(Diagnostic::new(
+                DiagnosticKind::RpcMissingHexSerde {
+                    type_name: struct_name.to_string(),
+                    field_name: field_name.to_string(),
+                    expected: expected_serde_as_value,
+                },
+                Some(self.span_at(field_name.span())),
+            ));
+        }
+    }
+
+    /// The individual `#[serde(...)]` and `#[serde_as(...)]` directives in
+    /// `attrs` that affect the encoding, sorted, e.g.
+    /// `#[serde(rename = "a", default)]` → `["default", "rename = \"a\""]`.
+    fn serde_directives(attrs: &[syn::Attribute]) -> Vec<String> {
+        // Directives that only affect the generated code, not the data
+        const IGNORED: &[&str] = &["bound", "crate", "expecting", "borrow", "getter"];
+        let mut directives = vec![];
+        for attr in attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("serde") || attr.path().is_ident("serde_as"))
+        {
+            if let syn::Meta::List(meta_list) = &attr.meta {
+                // Nested commas live inside groups, so splitting on top-level
+                // punctuation yields one directive per chunk.
+                let mut current = TokenStream::new();
+                for token in meta_list.tokens.clone() {
+                    match token {
+                        TokenTree::Punct(ref punct) if punct.as_char() == ',' => {
+                            directives.push(std::mem::take(&mut current).to_string());
+                        }
+                        token => current.extend([token]),
+                    }
+                }
+                if !current.is_empty() {
+                    directives.push(current.to_string());
+                }
+            }
+        }
+        directives.retain(|directive| {
+            let key = directive.split('=').next().unwrap_or_default().trim();
+            !IGNORED.contains(&key)
+        });
+        directives.sort();
+        directives.dedup();
+        directives
+    }
+
+    /// One `serde:` line per directive, see `serde_directives`.
+    fn serde_fingerprint(directives: &[String]) -> String {
+        directives
+            .iter()
+            .map(|directive| format!("serde:{}\n", directive))
+            .collect()
+    }
+
+    /// The path of the code a `with`, `serialize_with` or `deserialize_with`
+    /// directive names, e.g. `codec::hex` for `with = "codec::hex"`.
+    fn codec_path(directive: &str) -> Option<String> {
+        let (key, value) = directive.split_once('=')?;
+        if !matches!(key.trim(), "with" | "serialize_with" | "deserialize_with") {
+            return None;
+        }
+        Some(value.trim().trim_matches('"').replace(' ', ""))
+    }
+
+    /// The dependencies of a field of type `key` whose serde directives are
+    /// `serde`. A field encoded by custom code has none: its type goes to
+    /// `codec_deps` and the code to `codecs`. A flattened field's types are
+    /// also recorded in `flattened`.
+    fn field_deps(&mut self, key: &str, field: &syn::Field, serde: &[String]) -> Vec<String> {
+        let deps = self.calc_dep_types(field.ty.clone());
+        let codecs: Vec<String> = serde.iter().filter_map(|d| Self::codec_path(d)).collect();
+        if !codecs.is_empty() {
+            self.codecs
+                .entry(key.to_string())
+                .or_default()
+                .extend(codecs);
+            self.codec_deps
+                .entry(key.to_string())
+                .or_default()
+                .extend(deps);
+            return vec![];
+        }
+        if serde.iter().any(|directive| directive == "flatten") {
+            self.flattened
+                .entry(key.to_string())
+                .or_default()
+                .extend(deps.clone());
+        }
+        deps
+    }
+
+    fn field_schema(field: &syn::Field) -> FieldSchema {
+        let ty = &field.ty;
+        FieldSchema {
+            name: field.ident.as_ref().map(|ident| ident.to_string()),
+            ty: quote::quote! { #ty }.to_string(),
+            serde: Self::serde_directives(&field.attrs),
+        }
+    }
+
+    fn get_attr_tokens(&self, attr: &syn::Attribute) -> String {
+        match &attr.meta {
+            syn::Meta::List(meta_list) => meta_list.tokens.to_string(),
+            syn::Meta::NameValue(_meta_name_value) => "".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// The position of `span` in the file currently being visited.
+    fn span_at(&self, span: proc_macro2::Span) -> Span {
+        let start = span.start();
+        Span {
+            file: self.current_file.clone(),
+            line: Some(start.line),
+            // proc-macro2 columns are 0-based
+            column: Some(start.column + 1),
+        }
+    }
+
+    /// Record a type defined in the current module and return its key.
+    /// Where the type is defined is recorded preferring non-RPC locations:
+    /// if the type was previously recorded from an RPC file and we now see it
+    /// in a non-RPC file, overwrite the record.
+    fn record_type(&mut self, ident: &syn::Ident) -> String {
+        let key = path_key(&self.current_module, &ident.to_string());
+        self.types.push(key.clone());
+        let span = self.span_at(ident.span());
+        if self.in_rpc {
+            // Only insert if this type has never been seen before
+            self.type_span.entry(key.clone()).or_insert(span);
+        } else {
+            // Non-RPC file always takes priority — overwrite any previous entry
+            self.type_span.insert(key.clone(), span);
+        }
+        key
+    }
+
+    fn hash(fingerprint: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(fingerprint.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Whether a struct or variant has named, tuple or no fields, which
+    /// serde encodes differently even when there are no fields.
+    fn shape_fingerprint(fields: &Fields) -> String {
+        let shape = match fields {
+            Fields::Named(_) => "named",
+            Fields::Unnamed(_) => "tuple",
+            Fields::Unit => "unit",
+        };
+        format!("fields:{}\n", shape)
+    }
+
+    /// `field:id: u64` for named fields and `field:u64` for tuple fields,
+    /// with the type as in `fingerprint_type`.
+    fn field_fingerprint(&self, field: &syn::Field) -> String {
+        let ty = self.fingerprint_type(&field.ty);
+        match &field.ident {
+            Some(name) => format!("field:{}: {}\n", name, ty),
+            None => format!("field:{}\n", ty),
+        }
+    }
+
+    fn inner_visit_item_struct(&mut self, item_struct: &ItemStruct) {
+        let struct_name = item_struct.ident.to_string();
+        let key = self.record_type(&item_struct.ident);
+
+        if Self::has_derive(&item_struct.attrs, "Serialize") {
+            self.derive_serializable_types.insert(key.clone());
+        }
+        if Self::has_derive(&item_struct.attrs, "Deserialize") {
+            self.derive_deserializable_types.insert(key.clone());
+        }
+        if !self.in_rpc && Self::has_marker(&item_struct.attrs, "store_type") {
+            self.standalone_types.push(key.clone());
+        }
+
+        let mut fingerprint = String::new();
+        let mut legacy_fingerprint = String::new();
+
+        let container_serde = Self::serde_directives(&item_struct.attrs);
+        fingerprint.push_str(&format!("struct_name:{}\n", struct_name));
+        fingerprint.push_str(&self.enter_generics(&item_struct.generics));
+        fingerprint.push_str(&Self::serde_fingerprint(&container_serde));
+        fingerprint.push_str(&Self::shape_fingerprint(&item_struct.fields));
+        legacy_fingerprint.push_str(&format!("struct_name:{}\n", struct_name));
+
+        let mut dep_types = vec![];
+        let mut fields_schema = vec![];
+        for field in &item_struct.fields {
+            if self.in_rpc {
+                // RPC check runs on all fields (regardless of serde attrs)
+                self.check_rpc_field(&struct_name, field);
+            } else {
+                // For fingerprint/deps, skip fields with #[serde(skip)]
+                if self.should_skip_field(field) {
+                    continue;
+                }
+                let field_schema = Self::field_schema(field);
+                fingerprint.push_str(&self.field_fingerprint(field));
+                fingerprint.push_str(&Self::serde_fingerprint(&field_schema.serde));
+                if field.ident.is_some() {
+                    let field_type = quote::quote! { #field.ty }.to_string();
+                    let field_type = field_type.split(":").last().unwrap_or_default();
+                    legacy_fingerprint.push_str(&format!("field: {}\n", field_type));
+                }
+                dep_types.extend(self.field_deps(&key, field, &field_schema.serde));
+                fields_schema.push(field_schema);
+            }
+        }
+
+        if !self.in_rpc {
+            self.type_fingerprint
+                .insert(key.clone(), Self::hash(&fingerprint));
+            self.raw_fingerprint.insert(key.clone(), fingerprint);
+            self.legacy_fingerprint
+                .insert(key.clone(), Self::hash(&legacy_fingerprint));
+            self.type_schema.insert(
+                key.clone(),
+                TypeSchema::Struct {
+                    fields: fields_schema,
+                    serde: container_serde,
+                    custom_impl: None,
+                },
+            );
+            self.add_type_deps(&key, dep_types.clone());
+        }
+        self.generic_params.clear();
+    }
+
+    fn inner_visit_item_enum(&mut self, item_enum: &'_ syn::ItemEnum) {
+        let enum_name = item_enum.ident.to_string();
+        let mut dep_types = vec![];
+        let key = self.record_type(&item_enum.ident);
+
+        if Self::has_derive(&item_enum.attrs, "Serialize") {
+            self.derive_serializable_types.insert(key.clone());
+        }
+        if Self::has_derive(&item_enum.attrs, "Deserialize") {
+            self.derive_deserializable_types.insert(key.clone());
+        }
+
+        if !self.in_rpc && Self::has_marker(&item_enum.attrs, "store_type") {
+            self.standalone_types.push(key.clone());
+        }
+
+        let root = if self.in_rpc {
+            None
+        } else if let Some(spec) = self.matching_root(&enum_name) {
+            Some(spec)
+        } else if Self::has_marker(&item_enum.attrs, "migration_root") {
+            if !self.declared_roots.contains(&enum_name) {
+                self.declared_roots.push(enum_name.clone());
+            }
+            Some(enum_name.clone())
+        } else {
+            None
+        };
+
+        let mut fingerprint = String::new();
+        let mut legacy_fingerprint = String::new();
+        let container_serde = Self::serde_directives(&item_enum.attrs);
+        fingerprint.push_str(&format!("enum_name:{}\n", enum_name));
+        fingerprint.push_str(&self.enter_generics(&item_enum.generics));
+        fingerprint.push_str(&Self::serde_fingerprint(&container_serde));
+        // Positional and `repr`-based encoders write the discriminant, so the
+        // repr, explicit discriminants and the variant order all matter
+        let repr = item_enum
+            .attrs
+            .iter()
+            .find(|attr| attr.path().is_ident("repr"))
+            .map(|attr| self.get_attr_tokens(attr));
+        if let Some(repr) = &repr {
+            fingerprint.push_str(&format!("repr:{}\n", repr));
+        }
+        legacy_fingerprint.push_str(&format!("enum_name:{}\n", enum_name));
+
+        let mut variants_schema = vec![];
+        for variant in &item_enum.variants {
+            let variant_name = variant.ident.to_string();
+            fingerprint.push_str(&format!("variant:{}\n", variant_name));
+            let discriminant = variant
+                .discriminant
+                .as_ref()
+                .map(|(_, expr)| quote::quote! { #expr }.to_string());
+            if let Some(discriminant) = &discriminant {
+                fingerprint.push_str(&format!("discriminant:{}\n", discriminant));
+            }
+            fingerprint.push_str(&Self::shape_fingerprint(&variant.fields));
+            legacy_fingerprint.push_str(&format!("variant:{}\n", variant_name));
+
+            let mut variant_schema = VariantSchema {
+                name: variant_name.clone(),
+                fields: vec![],
+                serde: Self::serde_directives(&variant.attrs),
+                discriminant,
+            };
+            fingerprint.push_str(&Self::serde_fingerprint(&variant_schema.serde));
+            let mut variant_dep_types = vec![];
+            for field in &variant.fields {
+                if self.in_rpc {
+                    // RPC check runs on all fields (regardless of serde attrs)
+                    self.check_rpc_field(&enum_name, field);
+                } else {
+                    // For fingerprint/deps, skip fields with #[serde(skip)]
+                    if self.should_skip_field(field) {
+                        continue;
+                    }
+                    let field_schema = Self::field_schema(field);
+                    fingerprint.push_str(&self.field_fingerprint(field));
+                    fingerprint.push_str(&Self::serde_fingerprint(&field_schema.serde));
+                    let field_type = quote::quote! { #field.ty }.to_string();
+                    legacy_fingerprint.push_str(&format!("field:{}\n", field_type));
+                    variant_dep_types.extend(self.field_deps(&key, field, &field_schema.serde));
+                    variant_schema.fields.push(field_schema);
+                }
+            }
+            variants_schema.push(variant_schema);
+
+            if root.is_some() {
+                self.store_variants
+                    .push((key.clone(), variant_name, variant_dep_types.clone()));
+            }
+            dep_types.extend(variant_dep_types);
+        }
+
+        if !self.in_rpc {
+            self.type_fingerprint
+                .insert(key.clone(), Self::hash(&fingerprint));
+            self.raw_fingerprint.insert(key.clone(), fingerprint);
+            self.legacy_fingerprint
+                .insert(key.clone(), Self::hash(&legacy_fingerprint));
+            self.type_schema.insert(
+                key.clone(),
+                TypeSchema::Enum {
+                    variants: variants_schema,
+                    serde: container_serde,
+                    repr,
+                    custom_impl: None,
+                },
+            );
+            self.add_type_deps(&key, dep_types.clone());
+            if let Some(root) = root {
+                self.store_types.insert(root, key);
+            }
+        }
+        self.generic_params.clear();
+    }
+
+    /// Record the names a `use` tree brings into the current module, e.g.
+    /// `Hop` -> `crate::route::Hop` for `use crate::route::{Hop, Route}`.
+    /// `pub use` is recorded alike, so re-exports resolve through it.
+    fn record_use(&mut self, prefix: Vec<String>, tree: &syn::UseTree) {
+        match tree {
+            syn::UseTree::Path(use_path) => {
+                let mut prefix = prefix;
+                prefix.push(use_path.ident.to_string());
+                self.record_use(prefix, &use_path.tree);
+            }
+            syn::UseTree::Name(use_name) => {
+                let mut target = prefix;
+                // `use a::b::{self}` imports `b`
+                if use_name.ident != "self" {
+                    target.push(use_name.ident.to_string());
+                }
+                if let Some(name) = target.last().cloned() {
+                    self.module_imports().names.insert(name, target);
+                }
+            }
+            syn::UseTree::Rename(use_rename) => {
+                let mut target = prefix;
+                if use_rename.ident != "self" {
+                    target.push(use_rename.ident.to_string());
+                }
+                // `use Trait as _` brings no name into scope
+                if use_rename.rename != "_" {
+                    self.module_imports()
+                        .names
+                        .insert(use_rename.rename.to_string(), target);
+                }
+            }
+            syn::UseTree::Glob(_) => self.module_imports().globs.push(prefix),
+            syn::UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.record_use(prefix.clone(), tree);
+                }
+            }
+        }
+    }
+
+    fn module_imports(&mut self) -> &mut Imports {
+        self.imports.entry(self.current_module.clone()).or_default()
+    }
+
+    fn add_type_deps(&mut self, type_name: &str, dep_types: Vec<String>) {
+        let mut deps = dep_types.clone();
+        if !deps.is_empty() {
+            deps.sort();
+            deps.dedup();
+            self.type_deps
+                .entry(type_name.to_string())
+                .or_default()
+                .extend(deps.clone());
+        }
+    }
+
+    /// Returns true if `key` is defined in RPC code. Types defined there
+    /// are RPC-specific wrappers and should not shadow the canonical
+    /// definitions in the types crate.
+    fn is_rpc_type(&self, key: &str) -> bool {
+        self.type_span
+            .get(key)
+            .is_some_and(|span| self.rpc_files.contains(&span.file))
+    }
+
+    /// The keys of the types named `name`. Non-RPC definitions shadow RPC
+    /// wrappers of the same name.
+    fn types_named(&self, name: &str) -> Vec<&String> {
+        let keys = self
+            .by_name
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let canonical: Vec<&String> = keys.iter().filter(|k| !self.is_rpc_type(k)).collect();
+        if canonical.is_empty() {
+            keys.iter().collect()
+        } else {
+            canonical
+        }
+    }
+
+    /// Resolve a type path written in `module` to the key of its definition.
+    /// Failing an exact resolution, the only type with the same bare name as
+    /// the imported item is used.
+    fn resolve_path(&self, module: &[String], path: &[String]) -> Option<String> {
+        let mut visited = HashSet::new();
+        if let Some(key) = self.resolve_strict(
+            module,
+            path,
+            &|key| self.type_span.contains_key(key),
+            &mut visited,
+            0,
+        ) {
+            return Some(key);
+        }
+        match self
+            .types_named(&self.imported_name(module, path))
+            .as_slice()
+        {
+            [key] => Some((*key).clone()),
+            _ => None,
+        }
+    }
+
+    /// Resolve `crate::`, `self::` and `super::` paths, paths relative to
+    /// `module`, and paths from the crate root, in that order, to a key
+    /// `defined` accepts. Leading segments that name no module, like the
+    /// name of another scanned crate, are skipped.
+    fn resolve_strict(
+        &self,
+        module: &[String],
+        path: &[String],
+        defined: &dyn Fn(&str) -> bool,
+        visited: &mut HashSet<(Vec<String>, Vec<String>)>,
+        depth: usize,
+    ) -> Option<String> {
+        let first = path.first()?;
+        if depth > MAX_IMPORT_DEPTH {
+            return None;
+        }
+        match first.as_str() {
+            "crate" => return self.resolve_in(&[], &path[1..], defined, visited, depth),
+            "self" | "super" => {
+                let mut absolute = module.to_vec();
+                let mut rest = path;
+                while let Some(segment) = rest.first() {
+                    match segment.as_str() {
+                        "self" => {}
+                        "super" => {
+                            absolute.pop();
+                        }
+                        _ => break,
+                    }
+                    rest = &rest[1..];
+                }
+                return self.resolve_in(&absolute, rest, defined, visited, depth);
+            }
+            _ => {}
+        }
+        if let Some(key) = self.resolve_in(module, path, defined, visited, depth) {
+            return Some(key);
+        }
+        (0..path.len())
+            .find_map(|start| self.resolve_in(&[], &path[start..], defined, visited, depth))
+    }
+
+    /// Resolve `path` among the names visible in `module`: its own types,
+    /// its imports (including aliases and glob imports), and the items of
+    /// its child modules, which may be re-exported with `pub use`.
+    fn resolve_in(
+        &self,
+        module: &[String],
+        path: &[String],
+        defined: &dyn Fn(&str) -> bool,
+        visited: &mut HashSet<(Vec<String>, Vec<String>)>,
+        depth: usize,
+    ) -> Option<String> {
+        let first = path.first()?;
+        if !visited.insert((module.to_vec(), path.to_vec())) {
+            return None;
+        }
+        let key = [module, path].concat().join("::");
+        if defined(&key) {
+            return Some(key);
+        }
+        let imports = self.imports.get(module);
+        if let Some(target) = imports.and_then(|imports| imports.names.get(first)) {
+            let imported = [target.as_slice(), &path[1..]].concat();
+            if let Some(key) = self.resolve_strict(module, &imported, defined, visited, depth + 1) {
+                return Some(key);
+            }
+        }
+        if path.len() > 1 {
+            let child = [module, &path[..1]].concat();
+            if let Some(key) = self.resolve_in(&child, &path[1..], defined, visited, depth + 1) {
+                return Some(key);
+            }
+        }
+        for glob in imports.into_iter().flat_map(|imports| &imports.globs) {
+            let expanded = [glob.as_slice(), path].concat();
+            if let Some(key) = self.resolve_strict(module, &expanded, defined, visited, depth + 1) {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// The bare name of the item `path` refers to after following the
+    /// imports of `module`, e.g. `Byte32` for `Hash` with
+    /// `use ckb_types::packed::Byte32 as Hash`.
+    fn imported_name(&self, module: &[String], path: &[String]) -> String {
+        self.imported_path(module, path)
+            .last()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The path of the item `path` refers to after following the imports of
+    /// `module`, e.g. `ckb_types::packed::Byte32` for `Hash` with
+    /// `use ckb_types::packed::Byte32 as Hash`.
+    fn imported_path(&self, module: &[String], path: &[String]) -> Vec<String> {
+        let mut path = path.to_vec();
+        for _ in 0..MAX_IMPORT_DEPTH {
+            let target = path
+                .first()
+                .and_then(|first| self.imports.get(module)?.names.get(first));
+            match target {
+                Some(target) if target.len() > 1 || target[..] != path[..1] => {
+                    path = [target.as_slice(), &path[1..]].concat();
+                }
+                _ => break,
+            }
+        }
+        path
+    }
+
+    /// `fingerprint` written in `module` with each `⟦path⟧` replaced by the
+    /// key it refers to, or the imported path of an external type.
+    fn resolve_fingerprint(&self, module: &[String], fingerprint: &str) -> String {
+        let mut resolved = String::new();
+        let mut rest = fingerprint;
+        while let Some((before, after)) = rest.split_once('⟦') {
+            let Some((dep, after)) = after.split_once('⟧') else {
+                break;
+            };
+            let path: Vec<String> = dep.split("::").map(str::to_string).collect();
+            let target = self
+                .resolve_path(module, &path)
+                .unwrap_or_else(|| self.imported_path(module, &path).join("::"));
+            resolved.push_str(before);
+            resolved.push_str(&target);
+            rest = after;
+        }
+        resolved.push_str(rest);
+        resolved
+    }
+
+    /// The key `dep` (a path written in `module`) refers to, or the bare
+    /// name of the imported item for types that are not defined in the
+    /// scanned source.
+    fn resolve_dep(&self, module: &[String], dep: &str) -> String {
+        let path: Vec<String> = dep.split("::").map(str::to_string).collect();
+        self.resolve_path(module, &path)
+            .unwrap_or_else(|| self.imported_name(module, &path))
+    }
+
+    /// Resolve all recorded type paths to keys, and compute display names.
+    /// Runs once all files are scanned, as types may refer to types in files
+    /// visited later.
+    fn resolve(&mut self) {
+        for key in &self.types {
+            let keys = self.by_name.entry(bare_name(key).to_string()).or_default();
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+        self.display_names = self
+            .types
+            .iter()
+            .map(|key| {
+                let name = bare_name(key);
+                let shared = self.types_named(name).iter().any(|other| *other != key);
+                let display = if shared { key.as_str() } else { name };
+                (key.clone(), display.to_string())
+            })
+            .collect();
+
+        for (key, deps) in std::mem::take(&mut self.type_deps) {
+            let module = key_module(&key);
+            let deps = deps
+                .iter()
+                .map(|dep| self.resolve_dep(&module, dep))
+                .collect();
+            self.add_type_deps(&key, deps);
+        }
+        self.store_variants = std::mem::take(&mut self.store_variants)
+            .into_iter()
+            .map(|(root, variant, deps)| {
+                let module = key_module(&root);
+                let deps = deps
+                    .iter()
+                    .map(|dep| self.resolve_dep(&module, dep))
+                    .collect();
+                (root, variant, deps)
+            })
+            .collect();
+        for (module, ty) in std::mem::take(&mut self.serialize_impls) {
+            let key = self.resolve_dep(&module, &ty);
+            self.custom_serializable_types.insert(key);
+        }
+        for (module, ty) in std::mem::take(&mut self.deserialize_impls) {
+            let key = self.resolve_dep(&module, &ty);
+            self.custom_deserializable_types.insert(key);
+        }
+        for deps_by_type in [&mut self.codec_deps, &mut self.flattened] {
+            for deps in deps_by_type.values_mut() {
+                deps.sort();
+                deps.dedup();
+            }
+        }
+        let resolve_all = |visitor: &Self, deps_by_type: &HashMap<String, Vec<String>>| {
+            deps_by_type
+                .iter()
+                .map(|(key, deps)| {
+                    let module = key_module(key);
+                    let deps = deps
+                        .iter()
+                        .map(|dep| visitor.resolve_dep(&module, dep))
+                        .collect();
+                    (key.clone(), deps)
+                })
+                .collect()
+        };
+        self.codec_deps = resolve_all(self, &self.codec_deps);
+        self.flattened = resolve_all(self, &self.flattened);
+
+        // The impls with the helper functions of their module they call
+        let mut impl_code: HashMap<String, String> = HashMap::new();
+        for custom in &self.custom_impls {
+            let key = self.resolve_dep(&custom.module, &custom.ty);
+            let code = impl_code.entry(key).or_default();
+            code.push_str(&format!("impl:{}\n", custom.code));
+            for ident in &custom.idents {
+                if let Some(helper) = self.code_hashes.get(&path_key(&custom.module, ident)) {
+                    code.push_str(&format!("helper:{}:{}\n", ident, helper));
+                }
+            }
+        }
+        for (key, code) in impl_code {
+            let hash = Self::hash(&code);
+            if let Some(
+                TypeSchema::Struct { custom_impl, .. } | TypeSchema::Enum { custom_impl, .. },
+            ) = self.type_schema.get_mut(&key)
+            {
+                *custom_impl = Some(hash.clone());
+            }
+            self.impl_hashes.insert(key, hash);
+        }
+
+        for (key, fingerprint) in std::mem::take(&mut self.raw_fingerprint) {
+            let fingerprint = self.resolve_fingerprint(&key_module(&key), &fingerprint);
+            self.type_fingerprint.insert(key, Self::hash(&fingerprint));
+        }
+        let mut composed = HashMap::new();
+        for key in self.type_fingerprint.keys() {
+            self.compose_fingerprint(key, &mut composed, &mut HashSet::new());
+        }
+        self.type_fingerprint.extend(composed);
+    }
+
+    /// The key of the module or free function `path`, written in `module`,
+    /// refers to, if it is in the scanned source.
+    fn resolve_code(&self, module: &[String], path: &str) -> Option<String> {
+        let path: Vec<String> = path.split("::").map(str::to_string).collect();
+        let defined = |key: &str| self.code_hashes.contains_key(key);
+        self.resolve_strict(module, &path, &defined, &mut HashSet::new(), 0)
+    }
+
+    /// The fingerprint of `key` including the source of its custom codecs
+    /// and hand-written impls and the fingerprints of its flattened
+    /// children and the aliases it uses, so that changing any of them
+    /// changes the type's encoding.
+    /// A custom `Serialize` impl replaces the type's own fingerprint, as the
+    /// fields it writes are not known. Memoized in `composed`.
+    fn compose_fingerprint(
+        &self,
+        key: &str,
+        composed: &mut HashMap<String, String>,
+        visiting: &mut HashSet<String>,
+    ) -> Option<String> {
+        if let Some(finger) = composed.get(key) {
+            return Some(finger.clone());
+        }
+        let own = self.type_fingerprint.get(key)?;
+        if !visiting.insert(key.to_string()) {
+            return Some(own.clone());
+        }
+        let mut fingerprint = String::new();
+        let module = key_module(key);
+        for codec in self.codecs.get(key).into_iter().flatten() {
+            // Codecs from other crates are only tracked by path
+            if let Some(code) = self
+                .resolve_code(&module, codec)
+                .and_then(|code| self.code_hashes.get(&code))
+            {
+                fingerprint.push_str(&format!("with:{}:{}\n", codec, code));
+            }
+        }
+        for child in self.flattened.get(key).into_iter().flatten() {
+            if let Some(child) = self.compose_fingerprint(child, composed, visiting) {
+                fingerprint.push_str(&format!("flatten:{}\n", child));
+            }
+        }
+        for alias in self.type_deps.get(key).into_iter().flatten() {
+            if !self.aliases.contains(alias) {
+                continue;
+            }
+            if let Some(alias) = self.compose_fingerprint(alias, composed, visiting) {
+                fingerprint.push_str(&format!("alias:{}\n", alias));
+            }
+        }
+        if let Some(code) = self.impl_hashes.get(key) {
+            fingerprint.push_str(&format!("custom_impl:{}\n", code));
+        }
+        let finger = if self.custom_serializable_types.contains(key) {
+            Self::hash(&fingerprint)
+        } else if fingerprint.is_empty() {
+            own.clone()
+        } else {
+            Self::hash(&format!("{}\n{}", own, fingerprint))
+        };
+        composed.insert(key.to_string(), finger.clone());
+        Some(finger)
+    }
+
+    /// The name a type is reported under, see the type docs.
+    fn display(&self, key: &str) -> String {
+        self.display_names
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    /// The keys of the types `name` may refer to: a key, a display name, or
+    /// a path suffix like `channel::Channel`.
+    fn find(&self, name: &str) -> Vec<&String> {
+        if let Some((key, _)) = self.type_span.get_key_value(name) {
+            return vec![key];
+        }
+        let displayed: Vec<&String> = self
+            .display_names
+            .iter()
+            .filter(|(_, display)| *display == name)
+            .map(|(key, _)| key)
+            .collect();
+        if !displayed.is_empty() {
+            return displayed;
+        }
+        let suffix = format!("::{}", name);
+        self.types_named(bare_name(name))
+            .into_iter()
+            .filter(|key| key.ends_with(&suffix))
+            .collect()
+    }
+
+    /// The key `name` refers to, if it is unambiguous.
+    fn lookup(&self, name: &str) -> Option<&String> {
+        match self.find(name).as_slice() {
+            [key] => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Module path of `path` relative to the source directory `dir`: a
+    /// leading `src` is dropped, as are `lib.rs`, `main.rs` and `mod.rs`.
+    fn module_path(dir: &Path, path: &Path) -> Vec<String> {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let mut module: Vec<String> = relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if module.first().is_some_and(|m| m == "src") {
+            module.remove(0);
+        }
+        if module
+            .last()
+            .is_some_and(|m| m == "lib" || m == "main" || m == "mod")
+        {
+            module.pop();
+        }
+        module
+    }
+
+    fn visit_source_file(&mut self, dir: &Path, path: &Path, role: PathRole) -> Result<()> {
+        let file_path = path.to_string_lossy();
+        self.current_file = file_path.to_string();
+        let bytes = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let code = match String::from_utf8(bytes) {
+            Ok(code) => code,
+            Err(err) => {
+                let message = format!("file is not valid UTF-8: {}", err.utf8_error());
+                self.parse_failure(message, Span::file(file_path.to_string()));
+                if self.parse_mode != ParseMode::Recover {
+                    return Ok(());
+                }
+                String::from_utf8_lossy(err.as_bytes()).into_owned()
+            }
+        };
+        let mut file = match syn::parse_file(&code) {
+            Ok(file) => file,
+            Err(err) => {
+                let recovered = match self.parse_mode {
+                    ParseMode::Recover => Self::parse_items(&code),
+                    _ => None,
+                };
+                let Some((items, errors)) = recovered else {
+                    self.parse_failure(err.to_string(), self.span_at(err.span()));
+                    return Ok(());
+                };
+                if errors.is_empty() {
+                    self.parse_failure(err.to_string(), self.span_at(err.span()));
+                }
+                for (span, err) in errors {
+                    let message = format!("{} (only this item was skipped)", err);
+                    self.parse_failure(message, self.span_at(span));
+                }
+                syn::File {
+                    shebang: None,
+                    attrs: vec![],
+                    items,
+                }
+            }
+        };
+        if let Some(cfg) = &self.cfg {
+            cfg.configure(&mut file);
+        }
+        self.in_rpc = role == PathRole::Rpc;
+        if self.in_rpc {
+            self.rpc_files.insert(self.current_file.clone());
+        }
+        self.current_module = Self::module_path(dir, path);
+        self.code_hashes.insert(
+            self.current_module.join("::"),
+            Self::hash(&quote::quote! { #file }.to_string()),
+        );
+        self.visit_file(&file);
+        self.in_rpc = false;
+        Ok(())
+    }
+
+    /// Record that the current file could not be fully analyzed. This is an
+    /// error in strict mode and a warning otherwise.
+    fn parse_failure(&mut self, message: String, span: Span) {
+        let mut diagnostic = Diagnostic::new(DiagnosticKind::ParseFailure { message }, Some(span));
+        if self.parse_mode == ParseMode::Strict {
+            diagnostic.severity = Severity::Error;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Parse `code` item by item, for files that fail to parse as a whole,
+    /// e.g. because one item uses syntax syn does not support yet. Returns
+    /// the items that parse, and where each chunk that does not starts with
+    /// its error, or `None` if `code` cannot even be split into tokens.
+    ///
+    /// An item ends with a `;` or a `{ .. }` block. A block is only taken
+    /// to end a broken item if an item seems to follow, so that items like
+    /// `static A: T = T { .. };` are kept whole.
+    fn parse_items(code: &str) -> Option<RecoveredItems> {
+        const ITEM_STARTS: &[&str] = &[
+            "pub",
+            "struct",
+            "enum",
+            "union",
+            "type",
+            "fn",
+            "impl",
+            "trait",
+            "mod",
+            "use",
+            "const",
+            "static",
+            "extern",
+            "unsafe",
+            "async",
+            "macro_rules",
+        ];
+        let tokens: Vec<TokenTree> = code.parse::<TokenStream>().ok()?.into_iter().collect();
+        let is_punct = |token: Option<&TokenTree>, ch: char| matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == ch);
+        // Inner attributes like `#![allow(..)]` are not items
+        let mut start = 0;
+        while is_punct(tokens.get(start), '#') && is_punct(tokens.get(start + 1), '!') {
+            start += 3;
+        }
+
+        let mut items = vec![];
+        let mut errors = vec![];
+        let mut chunk: Vec<TokenTree> = vec![];
+        for (i, token) in tokens.iter().enumerate().skip(start) {
+            chunk.push(token.clone());
+            let is_block = matches!(
+                token,
+                TokenTree::Group(group) if group.delimiter() == proc_macro2::Delimiter::Brace
+            );
+            if !is_block && !is_punct(Some(token), ';') {
+                continue;
+            }
+            match syn::parse2::<syn::Item>(chunk.iter().cloned().collect()) {
+                Ok(item) => items.push(item),
+                Err(err) => {
+                    let item_follows = match tokens.get(i + 1) {
+                        None => true,
+                        Some(TokenTree::Punct(punct)) => punct.as_char() == '#',
+                        Some(TokenTree::Ident(ident)) => {
+                            ITEM_STARTS.contains(&ident.to_string().as_str())
+                        }
+                        _ => false,
+                    };
+                    if is_block && !item_follows {
+                        continue;
+                    }
+                    errors.push((Self::error_start(&chunk, &err), err));
+                }
+            }
+            chunk.clear();
+        }
+        if !chunk.is_empty() {
+            let stream: TokenStream = chunk.iter().cloned().collect();
+            if let Err(err) = syn::parse2::<syn::Item>(stream) {
+                errors.push((Self::error_start(&chunk, &err), err));
+            }
+        }
+        Some((items, errors))
+    }
+
+    /// Where `err` in `chunk` is, falling back to the start of the chunk
+    /// for errors at its end, which have no location.
+    fn error_start(chunk: &[TokenTree], err: &syn::Error) -> proc_macro2::Span {
+        if err.span().start().line > 0 {
+            err.span()
+        } else {
+            chunk.first().map_or(err.span(), TokenTree::span)
+        }
+    }
+
+    fn collect_fingerprints(
+        &self,
+        type_name: &str,
+        visited: &mut HashMap<String, bool>,
+        fingerprints: &mut BTreeMap<String, String>,
+    ) {
+        if visited.contains_key(type_name) {
+            return;
+        }
+
+        // Types with custom `impl Serialize` are fingerprinted by their impl
+        // (see `compose_fingerprint`). Still traverse their dependencies to
+        // find the actual serialized types. Dependencies from fields marked
+        // with `skip_store` have already been excluded when building type_deps.
+        if let Some(finger) = self.type_fingerprint.get(type_name) {
+            fingerprints.insert(type_name.to_string(), finger.clone());
+        }
+
+        visited.insert(type_name.to_string(), true);
+        if let Some(deps_vec) = self.type_deps.get(type_name) {
+            for dep in deps_vec {
+                self.collect_fingerprints(dep, visited, fingerprints);
+            }
+        }
+    }
+
+    /// Fingerprints and structure of every store type reachable from `root`,
+    /// or from any root if `root` is `None`. Standalone store types and
+    /// their dependencies are included if `standalone` is set.
+    pub(crate) fn construct_schema(&self, root: Option<&str>, standalone: bool) -> Schema {
+        let mut dump_fingers = BTreeMap::new();
+        let mut visited = HashMap::new();
+        let root_types = self
+            .store_types
+            .iter()
+            .filter(|(spec, _)| root.is_none_or(|root| root == spec.as_str()))
+            .filter_map(|(_, key)| self.type_deps.get(key))
+            .flatten();
+        let standalone_types = self.standalone_types.iter().filter(|_| standalone);
+        for type_name in root_types.chain(standalone_types) {
+            self.collect_fingerprints(type_name, &mut visited, &mut dump_fingers);
+        }
+        let types = dump_fingers
+            .keys()
+            .filter_map(|key| Some((self.display(key), self.type_schema.get(key)?.clone())))
+            .collect();
+        Schema {
+            fingerprints: dump_fingers
+                .into_iter()
+                .map(|(key, finger)| (self.display(&key), finger))
+                .collect(),
+            types,
+            ..Default::default()
+        }
+    }
+
+    /// Collect types reachable from the store types that are actually part of
+    /// serialized data. A type is included if:
+    /// 1. It is a direct variant type of a store root, OR
+    /// 2. It is a dependency of a type with `#[derive(Serialize)]`
+    ///
+    /// Types with custom `impl Serialize` are included but their field deps
+    /// are NOT followed (since we can't know which fields the custom impl
+    /// actually serializes). Likewise, the types of fields encoded by
+    /// `#[serde(with = "...")]` code are not followed, while the types of
+    /// `#[serde(flatten)]` fields are, as their fields are serialized inline.
+    ///
+    /// Types without any Serialize impl are NOT traversed — they appear in
+    /// fields that are never serialized (actor messages, error types, etc.).
+    fn collect_serializable_store_types(&self) -> HashSet<String> {
+        let builtin = self.builtin_types();
+        let mut result = HashSet::new();
+        let mut visited = HashSet::new();
+
+        fn collect_recursive(
+            visitor: &SynVisitor,
+            type_name: &str,
+            visited: &mut HashSet<String>,
+            result: &mut HashSet<String>,
+            builtin: &HashSet<&str>,
+        ) {
+            if visited.contains(type_name) || builtin.contains(type_name) {
+                return;
+            }
+            visited.insert(type_name.to_string());
+
+            // For types with custom `impl Serialize`: don't add to result,
+            // but still traverse their dependencies to find the actual serialized types.
+            // The dependencies have already been filtered to exclude skip_store fields.
+            let is_custom_serialize = visitor.custom_serializable_types.contains(type_name);
+            if !is_custom_serialize {
+                // Only include this type if it has a fingerprint (i.e., it was defined
+                // in the scanned source)
+                if visitor.type_fingerprint.contains_key(type_name) {
+                    result.insert(type_name.to_string());
+                }
+            }
+
+            // For types with derived Serialize or Deserialize, or custom impl Serialize:
+            // follow their field deps. For derived impls, we know all non-skipped
+            // fields are written or read. For custom Serialize, deps are already filtered.
+            if visitor.has_derived_serde(type_name)
+                || visitor.aliases.contains(type_name)
+                || is_custom_serialize
+            {
+                if let Some(deps_vec) = visitor.type_deps.get(type_name) {
+                    for dep in deps_vec {
+                        collect_recursive(visitor, dep, visited, result, builtin);
+                    }
+                }
+            }
+
+            // Types without any derived serde impl: don't traverse further.
+            // They appear in the dep graph but are not part of serialized data.
+        }
+
+        for type_name in self.store_entry_types() {
+            collect_recursive(self, type_name, &mut visited, &mut result, &builtin);
+        }
+        result
+    }
+
+    /// Whether `key` derives `Serialize` or `Deserialize`, so that all of
+    /// its non-skipped fields are written or read as stored data.
+    fn has_derived_serde(&self, key: &str) -> bool {
+        self.derive_serializable_types.contains(key)
+            || self.derive_deserializable_types.contains(key)
+    }
+
+    /// Check that every serialized store type can be read back: it must
+    /// derive or implement `Deserialize`. Returns one diagnostic per type
+    /// that does neither.
+    pub(crate) fn check_store_types_deserializable(&self) -> Vec<Diagnostic> {
+        let mut store_types: Vec<String> = self
+            .collect_serializable_store_types()
+            .into_iter()
+            .filter(|key| {
+                // An alias is read as its target, which is checked on its own
+                !self.aliases.contains(key)
+                    && !self.derive_deserializable_types.contains(key)
+                    && !self.custom_deserializable_types.contains(key)
+            })
+            .collect();
+        store_types.sort_by_key(|key| self.display(key));
+        store_types
+            .iter()
+            .map(|key| {
+                Diagnostic::new(
+                    DiagnosticKind::StoreTypeNotDeserializable {
+                        type_name: self.display(key),
+                        chains: self.try_find_type_chain(key, true),
+                    },
+                    self.type_span.get(key).cloned(),
+                )
+            })
+            .collect()
+    }
+
+    /// The types every store traversal starts from: the direct dependencies
+    /// of the root enums, and the standalone store types.
+    fn store_entry_types(&self) -> impl Iterator<Item = &String> {
+        self.store_types
+            .values()
+            .filter_map(|key| self.type_deps.get(key))
+            .flatten()
+            .chain(&self.standalone_types)
+    }
+
+    /// The configured external types, which are not followed.
+    fn builtin_types(&self) -> HashSet<&str> {
+        self.external_types.iter().map(String::as_str).collect()
+    }
+
+    /// Check that all types included in the migration schema are defined in
+    /// the types-dir. Only checks types that are serializable and reachable
+    /// from a store root. Returns one diagnostic per offending type.
+    pub(crate) fn check_store_types_in_types_dir(&self) -> Vec<Diagnostic> {
+        let types_dir = match &self.types_dir {
+            Some(d) => d,
+            None => return vec![], // no types-dir specified, skip check
+        };
+
+        let builtin = self.builtin_types();
+        let store_types = self.collect_serializable_store_types();
+        let mut store_types: Vec<&String> = store_types.iter().collect();
+        store_types.sort_by_key(|key| self.display(key));
+        let mut diagnostics = vec![];
+
+        for type_name in store_types {
+            // Skip builtin/external types
+            if builtin.contains(type_name.as_str()) {
+                continue;
+            }
+            // Check where this type is defined
+            if let Some(span) = self.type_span.get(type_name) {
+                if !span.file.contains(types_dir) {
+                    diagnostics.push(Diagnostic::new(
+                        DiagnosticKind::StoreTypeOutsideTypesDir {
+                            type_name: self.display(type_name),
+                            types_dir: types_dir.clone(),
+                            chains: self.try_find_type_chain(type_name, true),
+                        },
+                        Some(span.clone()),
+                    ));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Find dependency chains from store root variants to a target type.
+    /// Returns chains like: `KeyValue::PaymentHistoryTimedResult -> Direction`
+    /// or `KeyValue::PaymentSession -> PaymentData -> SomeType`. Chains from
+    /// a standalone store type start at the type itself, like `Blob -> Data`.
+    ///
+    /// If `serialize_aware` is true, only follows deps through derive-Serialize types
+    /// (matching the collect_serializable_store_types logic). Otherwise follows all deps.
+    pub(crate) fn try_find_type_chain(
+        &self,
+        target_type: &str,
+        serialize_aware: bool,
+    ) -> Vec<String> {
+        let builtin = self.builtin_types();
+        let target_type = self.lookup(target_type).map_or(target_type, String::as_str);
+        let mut result = vec![];
+
+        for (root_key, variant_name, variant_deps) in &self.store_variants {
+            // Check if this variant can reach target_type
+            for dep in variant_deps {
+                let mut visited = HashSet::new();
+                let mut chain = vec![format!("{}::{}", bare_name(root_key), variant_name)];
+                if self.find_chain_to_target(
+                    target_type,
+                    dep,
+                    &mut chain,
+                    &mut visited,
+                    &builtin,
+                    serialize_aware,
+                ) {
+                    result.push(self.display_chain(&chain));
+                }
+            }
+        }
+        for standalone in &self.standalone_types {
+            let mut visited = HashSet::new();
+            let mut chain = vec![];
+            if self.find_chain_to_target(
+                target_type,
+                standalone,
+                &mut chain,
+                &mut visited,
+                &builtin,
+                serialize_aware,
+            ) {
+                result.push(self.display_chain(&chain));
+            }
+        }
+
+        // Deduplicate chains
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    fn display_chain(&self, chain: &[String]) -> String {
+        chain
+            .iter()
+            .map(|key| self.display(key))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// DFS to find a chain from `current` to `target_type`.
+    /// If `serialize_aware` is true, only follows deps through derive-Serialize types
+    /// and skips custom-Serialize types; otherwise also follows the types of
+    /// fields encoded by custom code.
+    fn find_chain_to_target(
+        &self,
+        target_type: &str,
+        current: &str,
+        chain: &mut Vec<String>,
+        visited: &mut HashSet<String>,
+        builtin: &HashSet<&str>,
+        serialize_aware: bool,
+    ) -> bool {
+        if current == target_type {
+            chain.push(current.to_string());
+            return true;
+        }
+
+        if visited.contains(current) || builtin.contains(current) {
+            return false;
+        }
+        visited.insert(current.to_string());
+
+        if serialize_aware {
+            // Skip custom-Serialize types (we don't follow their deps)
+            if self.custom_serializable_types.contains(current) {
+                return false;
+            }
+
+            // Only follow deps for types with derived Serialize or Deserialize,
+            // and through aliases
+            if !self.has_derived_serde(current) && !self.aliases.contains(current) {
+                return false;
+            }
+        }
+
+        let codec_deps = if serialize_aware {
+            None
+        } else {
+            self.codec_deps.get(current)
+        };
+        if self.type_deps.contains_key(current) || codec_deps.is_some() {
+            let deps = self.type_deps.get(current).into_iter().flatten();
+            chain.push(current.to_string());
+            for dep in deps.chain(codec_deps.into_iter().flatten()) {
+                let mut branch_chain = chain.clone();
+                let mut branch_visited = visited.clone();
+                if self.find_chain_to_target(
+                    target_type,
+                    dep,
+                    &mut branch_chain,
+                    &mut branch_visited,
+                    builtin,
+                    serialize_aware,
+                ) {
+                    *chain = branch_chain;
+                    visited.extend(branch_visited);
+                    return true;
+                }
+            }
+            chain.pop(); // backtrack
+        }
+
+        false
+    }
+
+    /// Query a single type: whether it is reachable from a store root (store-related)
+    /// and if so, all dependency chains from the roots to it.
+    pub(crate) fn query_type(
+        &self,
+        type_name: &str,
+    ) -> std::result::Result<TypeQuery, Box<Diagnostic>> {
+        // First check if the type exists at all in the scanned source
+        let key = match self.find(type_name).as_slice() {
+            [key] => (*key).clone(),
+            [] => {
+                return Err(Box::new(Diagnostic::new(
+                    DiagnosticKind::TypeNotFound {
+                        type_name: type_name.to_string(),
+                        dirs: self.dirs.clone(),
+                    },
+                    None,
+                )));
+            }
+            keys => {
+                let mut candidates: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+                candidates.sort();
+                return Err(Box::new(Diagnostic::new(
+                    DiagnosticKind::AmbiguousType {
+                        type_name: type_name.to_string(),
+                        candidates,
+                    },
+                    None,
+                )));
+            }
+        };
+
+        // Check if it's reachable from a store root, also through fields
+        // encoded by custom code, whose types that code reads: the same set
+        // as `list_types`
+        let store_types = self.collect_all_store_reachable_types();
+        let store_related = store_types.contains(&key);
+        let mut query = TypeQuery {
+            type_name: self.display(&key),
+            store_related,
+            span: self.type_span.get(&key).cloned(),
+            chains: vec![],
+            store_deps: vec![],
+        };
+        if store_related {
+            // Dependency chains, serialize-aware to match the actual tracking
+            // unless the type is only reached through custom code
+            query.chains = self.try_find_type_chain(&key, true);
+            if query.chains.is_empty() {
+                query.chains = self.try_find_type_chain(&key, false);
+            }
+
+            // Also record what types this type depends on (that are also store types)
+            if let Some(deps) = self.type_deps.get(&key) {
+                query.store_deps = deps
+                    .iter()
+                    .filter(|d| store_types.contains(d.as_str()))
+                    .map(|d| self.display(d))
+                    .collect();
+            }
+        }
+        Ok(query)
+    }
+
+    /// Collect ALL type names reachable from a store root, following all deps
+    /// (not serialize-aware — includes everything in the transitive closure,
+    /// including the types of fields encoded by custom code).
+    fn collect_all_store_reachable_types(&self) -> HashSet<String> {
+        let builtin = self.builtin_types();
+        let mut visited = HashSet::new();
+
+        fn walk(
+            visitor: &SynVisitor,
+            type_name: &str,
+            visited: &mut HashSet<String>,
+            builtin: &HashSet<&str>,
+        ) {
+            if visited.contains(type_name) || builtin.contains(type_name) {
+                return;
+            }
+            // Only include types that were actually defined in scanned source
+            if !visitor.type_fingerprint.contains_key(type_name)
+                && !visitor.type_deps.contains_key(type_name)
+            {
+                return;
+            }
+            visited.insert(type_name.to_string());
+            let deps = visitor.type_deps.get(type_name).into_iter().flatten();
+            let codec_deps = visitor.codec_deps.get(type_name).into_iter().flatten();
+            for dep in deps.chain(codec_deps) {
+                walk(visitor, dep, visited, builtin);
+            }
+        }
+
+        for type_name in self.store_entry_types() {
+            walk(self, type_name, &mut visited, &builtin);
+        }
+        visited
+    }
+
+    /// Split all defined types into those reachable from a store root and those
+    /// that are not. Both lists are sorted by file path then by name.
+    pub(crate) fn list_types(&self) -> TypeListing {
+        let all_store_types = self.collect_all_store_reachable_types();
+        let builtin = self.builtin_types();
+
+        // Collect all defined types (with fingerprints, i.e., structs/enums),
+        // partitioned by whether they are in the store reachable set
+        let located = |store: bool| {
+            let mut list: Vec<TypeLocation> = self
+                .types
+                .iter()
+                .filter(|t| all_store_types.contains(t.as_str()) == store)
+                .filter(|t| !builtin.contains(t.as_str()))
+                .filter(|t| self.type_fingerprint.contains_key(t.as_str()))
+                .map(|t| TypeLocation {
+                    name: self.display(t),
+                    file: self.type_span.get(t).map(|span| span.file.clone()),
+                })
+                .collect();
+            // Sort by file path then by name
+            list.sort_by(|a, b| {
+                let fa = a.file.as_deref().unwrap_or("");
+                let fb = b.file.as_deref().unwrap_or("");
+                fa.cmp(fb).then(a.name.cmp(&b.name))
+            });
+            list.dedup();
+            list
+        };
+
+        TypeListing {
+            store_types: located(true),
+            non_store_types: located(false),
+        }
+    }
+
+    /// The legacy fingerprints of the types in `fingerprints`, which is
+    /// keyed by display name.
+    pub(crate) fn legacy_fingerprints(&self, fingerprints: &Fingerprints) -> Fingerprints {
+        fingerprints
+            .iter()
+            .map(|(name, finger)| {
+                let legacy = self
+                    .lookup(name)
+                    .and_then(|key| self.legacy_fingerprint.get(key));
+                (name.clone(), legacy.unwrap_or(finger).clone())
+            })
+            .collect()
+    }
+
+    /// Enums declared as roots with `#[migration_root]`, in the order seen.
+    pub(crate) fn declared_roots(&self) -> &[String] {
+        &self.declared_roots
+    }
+
+    /// Whether an enum matching the root spec was found.
+    pub(crate) fn has_root(&self, spec: &str) -> bool {
+        self.store_types.contains_key(spec)
+    }
+
+    /// Whether a type `type_name` (a key or display name) is defined.
+    pub(crate) fn is_defined(&self, type_name: &str) -> bool {
+        !self.find(type_name).is_empty()
+    }
+
+    /// The display names of the aliases folded into the fingerprint of
+    /// `type_name`, also through other aliases, see `compose_fingerprint`.
+    pub(crate) fn folded_aliases(&self, type_name: &str) -> Vec<String> {
+        let mut aliases = BTreeSet::new();
+        let mut pending: Vec<&String> = self.lookup(type_name).into_iter().collect();
+        while let Some(key) = pending.pop() {
+            for dep in self.type_deps.get(key).into_iter().flatten() {
+                if self.aliases.contains(dep) && aliases.insert(dep) {
+                    pending.push(dep);
+                }
+            }
+        }
+        aliases.into_iter().map(|key| self.display(key)).collect()
+    }
+
+    pub(crate) fn type_span(&self, type_name: &str) -> Option<&Span> {
+        self.type_span.get(self.lookup(type_name)?)
+    }
+
+    pub(crate) fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub(crate) fn walk_dir(&mut self) -> Result<()> {
+        let dirs = self.dirs.clone();
+        let paths = self.paths.compile()?;
+        let mut files = vec![];
+        for dir in &dirs {
+            let walk = WalkBuilder::new(dir)
+                .follow_links(true)
+                .hidden(false)
+                .ignore(false)
+                .git_global(false)
+                .git_ignore(self.paths.gitignore)
+                .git_exclude(self.paths.gitignore)
+                .parents(self.paths.gitignore)
+                .require_git(false)
+                .build();
+            for entry in walk {
+                match entry {
+                    Ok(ref e)
+                        if !e.file_name().to_string_lossy().starts_with('.')
+                            && e.file_name().to_string_lossy().ends_with(".rs") =>
+                    {
+                        let dir = PathBuf::from(dir);
+                        let role = paths.role(&dir, e.path());
+                        if role != PathRole::Ignore {
+                            files.push((e.path().to_owned(), dir, role));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // different order may produce different hash
+        files.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        for (file_path, dir, role) in files {
+            self.visit_source_file(&dir, &file_path, role)?;
+        }
+        self.resolve();
+        Ok(())
+    }
+}
+
+impl Visit<'_> for SynVisitor {
+    fn visit_item_struct(&mut self, item_struct: &ItemStruct) {
+        self.inner_visit_item_struct(item_struct);
+    }
+
+    fn visit_item(&mut self, item: &syn::Item) {
+        match item {
+            syn::Item::Struct(item_struct) => self.inner_visit_item_struct(item_struct),
+            syn::Item::Enum(item_enum) => self.visit_item_enum(item_enum),
+            syn::Item::Type(item_type) => {
+                let key = self.record_type(&item_type.ident);
+                let generics = self.enter_generics(&item_type.generics);
+                let type_deps = self.calc_dep_types(*item_type.ty.clone());
+                self.add_type_deps(&key, type_deps.clone());
+                let target_fingerprint = self.fingerprint_type(&item_type.ty);
+                self.generic_params.clear();
+                if !self.in_rpc {
+                    let ty = &item_type.ty;
+                    let target = quote::quote! { #ty }.to_string();
+                    let fingerprint = format!(
+                        "alias_name:{}\n{}target:{}\n",
+                        item_type.ident, generics, target_fingerprint
+                    );
+                    self.type_fingerprint
+                        .insert(key.clone(), Self::hash(&fingerprint));
+                    self.raw_fingerprint.insert(key.clone(), fingerprint);
+                    self.type_schema
+                        .insert(key.clone(), TypeSchema::Alias { target });
+                    self.aliases.insert(key);
+                }
+            }
+            syn::Item::Impl(item_impl) => {
+                // Detect `impl Serialize for TypeName` to track custom Serialize impls,
+                // and record the code of custom Serialize and Deserialize impls
+                if let Some((_, ref trait_path, _)) = item_impl.trait_ {
+                    let trait_name = trait_path
+                        .segments
+                        .last()
+                        .map(|s| s.ident.to_string())
+                        .unwrap_or_default();
+                    if trait_name == "Serialize" || trait_name == "Deserialize" {
+                        if let Type::Path(ref type_path) = *item_impl.self_ty {
+                            let path = type_path
+                                .path
+                                .segments
+                                .iter()
+                                .map(|seg| seg.ident.to_string())
+                                .collect::<Vec<_>>()
+                                .join("::");
+                            let impls = if trait_name == "Serialize" {
+                                &mut self.serialize_impls
+                            } else {
+                                &mut self.deserialize_impls
+                            };
+                            impls.push((self.current_module.clone(), path.clone()));
+                            let tokens = quote::quote! { #item_impl };
+                            let mut idents = BTreeSet::new();
+                            token_idents(tokens.clone(), &mut idents);
+                            self.custom_impls.push(CustomImpl {
+                                module: self.current_module.clone(),
+                                ty: path,
+                                code: tokens.to_string(),
+                                idents,
+                            });
+                        }
+                    }
+                }
+            }
+            syn::Item::Fn(item_fn) => {
+                let key = path_key(&self.current_module, &item_fn.sig.ident.to_string());
+                self.code_hashes
+                    .insert(key, Self::hash(&quote::quote! { #item_fn }.to_string()));
+            }
+            syn::Item::Use(item_use) => self.record_use(vec![], &item_use.tree),
+            syn::Item::Mod(item_mod) => {
+                // `mod foo;` is covered by the file layout. A configured
+                // cfg set has already dropped inactive modules; without
+                // one, inline modules are still evaluated with no options
+                // enabled, so `#[cfg(test)]` modules are left out
+                let enabled = self.cfg.is_some() || CfgSet::new().is_enabled(&item_mod.attrs);
+                if let Some((_, items)) = &item_mod.content {
+                    if enabled {
+                        self.current_module.push(item_mod.ident.to_string());
+                        self.code_hashes.insert(
+                            self.current_module.join("::"),
+                            Self::hash(&quote::quote! { #(#items)* }.to_string()),
+                        );
+                        for item in items {
+                            self.visit_item(item);
+                        }
+                        self.current_module.pop();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_item_enum(&mut self, item_enum: &'_ syn::ItemEnum) {
+        self.inner_visit_item_enum(item_enum);
+    }
+}